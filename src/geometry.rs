@@ -0,0 +1,234 @@
+/// Physical keyboard geometry: per-key coordinates and physical role.
+///
+/// `penalty.rs` used to hardwire ergonomic relationships ("same finger",
+/// "stretch", "long jump", "roll in/out") as enumerated `pos == X && pos
+/// == Y` pairs tuned for one 50-slot ErgoDox-like board. This module
+/// describes the physical layer those relationships are actually derived
+/// from, so the penalty functions can compute them from geometry
+/// (Euclidean distance, row delta, finger identity) instead of a table of
+/// magic position pairs. The numbers that used to be baked into
+/// `layout.rs` (`KEY_FINGERS`, `KEY_HANDS`, `KEY_ROWS`,
+/// `KEY_CENTER_COLUMN`) are now one built-in profile, `ansi`.
+///
+/// This also used to be split across this file and a separate
+/// `layout::KeyboardProfile` -- one loaded for `Layout::with_profile`
+/// (finger/hand/row/center/swappable), the other loaded for the scoring
+/// functions in `penalty.rs` (finger/hand/row/x/y) -- describing the same
+/// physical board through two independent types and on-disk formats that
+/// nothing checked agreed with each other. `center`/`swappable` live here
+/// too now, so there's exactly one board description, loaded once and
+/// threaded everywhere.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use layout::KeyMap;
+use layout::Finger;
+use layout::Hand;
+use layout::Row;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct KeyGeometry
+{
+    pub x:      f64,
+    pub y:      f64,
+    pub finger: Finger,
+    pub hand:   Hand,
+    pub row:    Row,
+
+    /// Whether this position is in the board's center column, relevant to
+    /// a couple of the stretch-style penalties in `penalty.rs`.
+    pub center: bool,
+
+    /// Whether the annealer is allowed to swap a letter onto this
+    /// position at all. See `layout::Layout::swappable_positions`.
+    pub swappable: bool,
+}
+
+#[derive(Clone)]
+pub struct KeyboardGeometry(pub KeyMap<KeyGeometry>);
+
+// Row stagger, in key-widths, matching the row-staggered ANSI profile
+// that used to be hardcoded into `layout.rs`. Positions 0..36 are the three
+// finger rows (left hand 0..6, right hand 6..12 per row); positions 36..50
+// are the thumb cluster, which has no meaningful row stagger.
+const ANSI_PROFILE: [(f64, f64); 50] = [
+    (0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0), (4.0, 0.0), (5.0, 0.0),
+    (6.0, 0.0), (7.0, 0.0), (8.0, 0.0), (9.0, 0.0), (10.0, 0.0), (11.0, 0.0),
+    (0.25, 1.0), (1.25, 1.0), (2.25, 1.0), (3.25, 1.0), (4.25, 1.0), (5.25, 1.0),
+    (6.25, 1.0), (7.25, 1.0), (8.25, 1.0), (9.25, 1.0), (10.25, 1.0), (11.25, 1.0),
+    (0.75, 2.0), (1.75, 2.0), (2.75, 2.0), (3.75, 2.0), (4.75, 2.0), (5.75, 2.0),
+    (6.75, 2.0), (7.75, 2.0), (8.75, 2.0), (9.75, 2.0), (10.75, 2.0), (11.75, 2.0),
+    (3.0, 3.0), (4.0, 3.0), (5.0, 3.0), (6.0, 3.0), (7.0, 3.0),
+    (8.0, 3.0), (9.0, 3.0), (10.0, 3.0), (11.0, 3.0), (12.0, 3.0),
+    (5.0, 4.0), (6.0, 4.0), (9.0, 4.0), (10.0, 4.0),
+];
+
+impl KeyboardGeometry
+{
+    /// The built-in ANSI-derived profile. This reproduces the same
+    /// finger/hand/row/center assignment as the static tables `layout.rs`
+    /// used to carry, plus the (x, y) coordinates those tables never had an
+    /// explicit form for, and a swappable mask that pins only position 11
+    /// (the outer key of the top finger row), matching what
+    /// `LAYOUT_MASK_SWAP_OFFSETS` pinned before.
+    pub fn ansi()
+    -> KeyboardGeometry
+    {
+        let fingers = [
+            Finger::Pinky, Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
+            Finger::Pinky, Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
+            Finger::Pinky, Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
+            Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb,
+            Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb];
+        let hands = [
+            Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
+            Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
+            Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
+            Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
+            Hand::Left, Hand::Left, Hand::Right, Hand::Right];
+        let rows = [
+            Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,       Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,
+            Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,      Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,
+            Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,    Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,
+            Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome,
+            Row::ThumbTop, Row::ThumbTop, Row::ThumbTop, Row::ThumbTop];
+        let centers = [
+            false, false, false, false, false, true,    true, false, false, false, false, false,
+            false, false, false, false, false, true,    true, false, false, false, false, false,
+            false, false, false, false, false, true,    true, false, false, false, false, false,
+                   false, false, false, false, false,   false, false, false, false, false,
+                                        false, false,   false, false];
+        let swappable = [
+            true, true, true, true, true, true,    true, true, true, true, true, false,
+            true, true, true, true, true, true,    true, true, true, true, true, true,
+            true, true, true, true, true, true,    true, true, true, true, true, true,
+                  true, true, true, true, true,    true, true, true, true, true,
+                                       true, true,   true, true];
+
+        let mut keys = [KeyGeometry {
+            x: 0.0, y: 0.0, finger: Finger::Index, hand: Hand::Left, row: Row::Home,
+            center: false, swappable: true,
+        }; 50];
+        for i in 0..50 {
+            keys[i] = KeyGeometry {
+                x:         ANSI_PROFILE[i].0,
+                y:         ANSI_PROFILE[i].1,
+                finger:    fingers[i],
+                hand:      hands[i],
+                row:       rows[i],
+                center:    centers[i],
+                swappable: swappable[i],
+            };
+        }
+
+        KeyboardGeometry(KeyMap(keys))
+    }
+
+    /// Loads a profile from a simple whitespace-separated text format,
+    /// one key per line, in position order: `x y finger hand row center
+    /// swappable`, e.g. `4.25 1.0 index left home false true`. Lines
+    /// starting with `#` and blank lines are ignored. The Nth key line
+    /// describes position N; positions past the last key line keep the
+    /// ANSI default for that slot, so a profile only needs to list keys
+    /// up to the last position it overrides.
+    pub fn load(path: &str)
+    -> Result<KeyboardGeometry, String>
+    {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut geometry = KeyboardGeometry::ansi();
+        let KeyMap(ref mut keys) = geometry.0;
+
+        let mut pos = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("{}: {}", path, e))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if pos >= keys.len() {
+                return Err(format!("{}: too many key rows (expected {})", path, keys.len()));
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 7 {
+                return Err(format!("{}: expected 7 fields, got {}", path, fields.len()));
+            }
+
+            keys[pos] = KeyGeometry {
+                x:         fields[0].parse().map_err(|_| format!("{}: bad x", path))?,
+                y:         fields[1].parse().map_err(|_| format!("{}: bad y", path))?,
+                finger:    parse_finger(fields[2])?,
+                hand:      parse_hand(fields[3])?,
+                row:       parse_row(fields[4])?,
+                center:    fields[5].parse().map_err(|_| format!("{}: bad center", path))?,
+                swappable: fields[6].parse().map_err(|_| format!("{}: bad swappable", path))?,
+            };
+            pos += 1;
+        }
+
+        Ok(geometry)
+    }
+
+    /// Euclidean distance, in key-widths, between two positions.
+    pub fn distance(&self, a: usize, b: usize)
+    -> f64
+    {
+        let KeyMap(ref keys) = self.0;
+        let dx = keys[a].x - keys[b].x;
+        let dy = keys[a].y - keys[b].y;
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    pub fn key(&self, pos: usize)
+    -> &KeyGeometry
+    {
+        let KeyMap(ref keys) = self.0;
+        &keys[pos]
+    }
+
+    /// Every swappable position, in position order. See
+    /// `layout::Layout::swappable_positions`, the only caller.
+    pub fn swappable_positions(&self)
+    -> Vec<usize>
+    {
+        let KeyMap(ref keys) = self.0;
+        (0..keys.len()).filter(|&pos| keys[pos].swappable).collect()
+    }
+}
+
+pub(crate) fn parse_finger(s: &str)
+-> Result<Finger, String>
+{
+    match s {
+        "thumb"  => Ok(Finger::Thumb),
+        "index"  => Ok(Finger::Index),
+        "middle" => Ok(Finger::Middle),
+        "ring"   => Ok(Finger::Ring),
+        "pinky"  => Ok(Finger::Pinky),
+        _ => Err(format!("unknown finger '{}'", s)),
+    }
+}
+
+pub(crate) fn parse_hand(s: &str)
+-> Result<Hand, String>
+{
+    match s {
+        "left"  => Ok(Hand::Left),
+        "right" => Ok(Hand::Right),
+        _ => Err(format!("unknown hand '{}'", s)),
+    }
+}
+
+pub(crate) fn parse_row(s: &str)
+-> Result<Row, String>
+{
+    match s {
+        "top"        => Ok(Row::Top),
+        "home"       => Ok(Row::Home),
+        "bottom"     => Ok(Row::Bottom),
+        "thumb_top"  => Ok(Row::ThumbTop),
+        "thumb_home" => Ok(Row::ThumbHome),
+        _ => Err(format!("unknown row '{}'", s)),
+    }
+}
@@ -0,0 +1,312 @@
+/// Incremental re-scoring for the layout search: avoids rescanning the
+/// whole corpus after every candidate swap.
+///
+/// Scoring a full corpus from scratch after every swap (as
+/// `penalty::calculate_penalty` does) is the dominant cost of layout
+/// search. Chess engines avoid the equivalent cost with incremental
+/// evaluation (and NNUE accumulators) that update only the deltas
+/// touched by a move; this does the same thing here. The corpus is
+/// reduced to a bigram frequency matrix once, up front, and a swap only
+/// has to recompute the rows/columns of the penalty contribution cache
+/// that touch the two swapped positions, turning an O(corpus) step into
+/// O(num positions) per swap.
+///
+/// This only covers the two-key penalties in `penalty.rs` (base, same
+/// finger, stretch, roll in/out, same key) via `penalty::two_key_penalty`
+/// and `penalty::base_penalty` -- the three- and four-key penalties (roll
+/// reversal, twist, pinky/ring alternation, same/alternating hand) need
+/// more context than a single bigram and still require a full corpus
+/// pass through `penalty::calculate_penalty`.
+
+use std::collections::HashMap;
+
+use layout::Layout;
+use layout::KeyPress;
+use geometry::KeyboardGeometry;
+use penalty;
+use penalty::TaperedPenaltyTables;
+
+const NUM_POSITIONS: usize = 50;
+
+pub struct IncrementalScorer<'a>
+{
+    geometry: &'a KeyboardGeometry,
+    tables:   &'a TaperedPenaltyTables,
+
+    char_freq:   HashMap<char, f64>,
+    bigram_freq: HashMap<(char, char), f64>,
+
+    pos_to_char:  [char; NUM_POSITIONS],
+    contribution: [[f64; NUM_POSITIONS]; NUM_POSITIONS],
+    total:        f64,
+
+    debug: bool,
+}
+
+impl <'a> IncrementalScorer<'a>
+{
+    /// Builds the scorer for `layout`, scoring against `corpus`. Computes
+    /// the one-time corpus bigram frequency matrix and the initial
+    /// per-position contribution cache. When `debug` is set, `swap`
+    /// asserts that the incrementally updated total still matches a full
+    /// recompute.
+    pub fn new(
+        corpus:   &str,
+        layout:   &Layout,
+        geometry: &'a KeyboardGeometry,
+        tables:   &'a TaperedPenaltyTables,
+        debug:        bool)
+    -> IncrementalScorer<'a>
+    {
+        // `pos_to_char` below only ever holds the base layer's (lowercase)
+        // char for a position -- `Layout::get_char` always reads layer 0
+        // -- so case-fold every corpus char here too. Otherwise a
+        // capitalized char's frequency is keyed under a char `pos_to_char`
+        // never contains, and its base/bigram contribution silently never
+        // gets counted.
+        let mut char_freq: HashMap<char, f64> = HashMap::new();
+        let mut bigram_freq: HashMap<(char, char), f64> = HashMap::new();
+        let mut prev: Option<char> = None;
+        for raw_c in corpus.chars() {
+            let c = fold_case(raw_c);
+            *char_freq.entry(c).or_insert(0.0) += 1.0;
+            if let Some(p) = prev {
+                *bigram_freq.entry((p, c)).or_insert(0.0) += 1.0;
+            }
+            prev = Some(c);
+        }
+
+        let mut pos_to_char = ['\0'; NUM_POSITIONS];
+        for pos in 0..NUM_POSITIONS {
+            pos_to_char[pos] = layout.get_char(pos);
+        }
+
+        let mut scorer = IncrementalScorer {
+            geometry:     geometry,
+            tables:       tables,
+            char_freq:    char_freq,
+            bigram_freq:  bigram_freq,
+            pos_to_char:  pos_to_char,
+            contribution: [[0.0; NUM_POSITIONS]; NUM_POSITIONS],
+            total:        0.0,
+            debug:        debug,
+        };
+
+        for old1 in 0..NUM_POSITIONS {
+            for curr in 0..NUM_POSITIONS {
+                if old1 == curr {
+                    continue;
+                }
+                let value = scorer.bigram_contribution(old1, curr);
+                scorer.contribution[old1][curr] = value;
+                scorer.total += value;
+            }
+        }
+        for pos in 0..NUM_POSITIONS {
+            scorer.total += scorer.base_contribution(pos);
+        }
+
+        scorer
+    }
+
+    /// The current total penalty across every cached contribution.
+    pub fn total(&self)
+    -> f64
+    {
+        self.total
+    }
+
+    /// Swaps the letters assigned to positions `i` and `j`, updating
+    /// `total` by recomputing only the rows/columns of the contribution
+    /// cache touched by the swap instead of rescanning the corpus. In
+    /// debug mode, asserts the result against a full recompute.
+    pub fn swap(&mut self, i: usize, j: usize)
+    {
+        if i == j {
+            return;
+        }
+
+        // `remove_position(i)`/`remove_position(j)` below only clear the
+        // contributions each position has with every *other* position --
+        // the `i`-`j` cross term is shared between them, so it's removed
+        // once here instead of once inside each call (which would
+        // double-subtract it). Same story for `add_position` afterwards.
+        self.remove_position(i, j);
+        self.remove_position(j, i);
+        self.total -= self.contribution[i][j] + self.contribution[j][i];
+
+        self.pos_to_char.swap(i, j);
+
+        self.add_position(i, j);
+        self.add_position(j, i);
+        let forward = self.bigram_contribution(i, j);
+        self.contribution[i][j] = forward;
+        self.total += forward;
+        let backward = self.bigram_contribution(j, i);
+        self.contribution[j][i] = backward;
+        self.total += backward;
+
+        if self.debug {
+            let recomputed = self.recompute_total();
+            assert!((recomputed - self.total).abs() < 1e-6,
+                "incremental total {} diverged from recompute {}", self.total, recomputed);
+        }
+    }
+
+    /// Recomputes the total from scratch; used to validate the
+    /// incremental bookkeeping in debug mode.
+    pub fn recompute_total(&self)
+    -> f64
+    {
+        let mut total = 0.0;
+        for pos in 0..NUM_POSITIONS {
+            total += self.base_contribution(pos);
+        }
+        for old1 in 0..NUM_POSITIONS {
+            for curr in 0..NUM_POSITIONS {
+                if old1 == curr {
+                    continue;
+                }
+                total += self.bigram_contribution(old1, curr);
+            }
+        }
+        total
+    }
+
+    /// Subtracts every cached contribution involving `pos` from `total`,
+    /// except the pair shared with `skip` -- the caller owns that one so
+    /// it's only touched once across both positions in a swap.
+    fn remove_position(&mut self, pos: usize, skip: usize)
+    {
+        self.total -= self.base_contribution(pos);
+        for other in 0..NUM_POSITIONS {
+            if other == pos || other == skip {
+                continue;
+            }
+            self.total -= self.contribution[pos][other] + self.contribution[other][pos];
+        }
+    }
+
+    /// Recomputes every contribution involving `pos` against the
+    /// (already updated) `pos_to_char`, caches it, and adds it back into
+    /// `total`, except the pair shared with `skip` (see `remove_position`).
+    fn add_position(&mut self, pos: usize, skip: usize)
+    {
+        self.total += self.base_contribution(pos);
+        for other in 0..NUM_POSITIONS {
+            if other == pos || other == skip {
+                continue;
+            }
+            let forward = self.bigram_contribution(pos, other);
+            self.contribution[pos][other] = forward;
+            self.total += forward;
+
+            let backward = self.bigram_contribution(other, pos);
+            self.contribution[other][pos] = backward;
+            self.total += backward;
+        }
+    }
+
+    /// The one-key base penalty for the char at `pos`, plus the same-key
+    /// penalty for that char typed twice in a row. Both depend on `pos`
+    /// alone, so they're priced here rather than on the diagonal of
+    /// `contribution`, which is never filled.
+    fn base_contribution(&self, pos: usize)
+    -> f64
+    {
+        let c = self.pos_to_char[pos];
+        let freq = *self.char_freq.get(&c).unwrap_or(&0.0);
+        penalty::base_penalty(pos) * freq + self.bigram_contribution(pos, pos)
+    }
+
+    fn bigram_contribution(&self, old1_pos: usize, curr_pos: usize)
+    -> f64
+    {
+        let old1_char = self.pos_to_char[old1_pos];
+        let curr_char = self.pos_to_char[curr_pos];
+        let freq = *self.bigram_freq.get(&(old1_char, curr_char)).unwrap_or(&0.0);
+        if freq == 0.0 {
+            return 0.0;
+        }
+
+        let old1 = key_press_at(old1_pos, old1_char, self.geometry);
+        let curr = key_press_at(curr_pos, curr_char, self.geometry);
+        penalty::two_key_penalty(&curr, &old1, self.geometry, self.tables) * freq
+    }
+}
+
+/// The base-layer char `c` lands on: every built-in layout mirrors its
+/// uppercase layer onto the base layer position-for-position (e.g. `T`
+/// over `t`), so lowercasing folds a capital letter back onto the char
+/// `pos_to_char` actually stores. Shifted punctuation (e.g. `:` over `;`)
+/// has no lowercase form, so it is returned unchanged and matches no
+/// position.
+fn fold_case(c: char)
+-> char
+{
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Builds the `KeyPress` the penalty functions expect for `pos`, reading
+/// finger/hand/row/center off the loaded `KeyboardGeometry` instead of a
+/// layer's own static tables. `layer`/`activator_pos` are always
+/// base-layer values: `pos_to_char` only ever tracks the base layer (see
+/// `IncrementalScorer::new`), so every position scored here is free to
+/// reach and the layer-switch penalty never applies.
+fn key_press_at(pos: usize, kc: char, geometry: &KeyboardGeometry)
+-> KeyPress
+{
+    let key = geometry.key(pos);
+    KeyPress {
+        kc:     kc,
+        pos:    pos,
+        finger: key.finger,
+        hand:   key.hand,
+        row:    key.row,
+        center: key.center,
+        layer:  0,
+        activator_pos: None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use layout;
+    use geometry::KeyboardGeometry;
+    use penalty::TaperedPenaltyTables;
+
+    // Regression test for a double-counted i-j cross term in `swap`:
+    // with the bug, `debug`'s recompute assertion fires within the first
+    // few swaps against a real corpus.
+    #[test]
+    fn swap_matches_full_recompute()
+    {
+        let corpus = "the quick brown fox jumps over the lazy dog";
+        let geometry = KeyboardGeometry::ansi();
+        let tables = TaperedPenaltyTables::new(&geometry, 0.5);
+        let mut layout = layout::qwerty_layout();
+        let mut scorer = IncrementalScorer::new(corpus, &layout, &geometry, &tables, true);
+
+        for _ in 0..10 {
+            let (i, j) = layout.shuffle_swap().unwrap();
+            scorer.swap(i, j);
+        }
+    }
+
+    // Regression test for capitalized chars/bigrams being keyed under a
+    // char `pos_to_char` (base layer only) never contains, so their
+    // contribution silently dropped out of `total`.
+    #[test]
+    fn capitalized_chars_are_folded_into_base_layer_counts()
+    {
+        let geometry = KeyboardGeometry::ansi();
+        let tables = TaperedPenaltyTables::new(&geometry, 0.5);
+        let layout = layout::qwerty_layout();
+        let scorer = IncrementalScorer::new("Tt", &layout, &geometry, &tables, false);
+
+        assert_eq!(*scorer.char_freq.get(&'t').unwrap(), 2.0);
+        assert_eq!(*scorer.bigram_freq.get(&('t', 't')).unwrap(), 1.0);
+    }
+}
@@ -2,9 +2,14 @@
 
 extern crate rand;
 
+use std::collections::HashMap;
 use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use self::rand::random;
 
+use geometry::KeyboardGeometry;
+
 /* ----- *
  * TYPES *
  * ----- */
@@ -32,20 +37,84 @@ impl <T: Copy> Clone for KeyMap<T>
 #[derive(Clone)]
 pub struct Layer(KeyMap<char>);
 
+/// How an `Activator` switches to its target layer: `Momentary` only while
+/// the activation key is held, `Sticky` for the single keypress following
+/// one tap.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ActivationMode
+{
+	Momentary,
+	Sticky,
+}
+
+/// A base-layer position that switches typing to `target_layer` instead of
+/// a letter. `shuffle_position`/`LayoutPermutations` keep these positions
+/// pinned so the optimizer only rearranges letters, never the layer
+/// switches themselves.
+#[derive(Clone, Copy)]
+pub struct Activator
+{
+	pub pos:          usize,
+	pub target_layer: usize,
+	pub mode:         ActivationMode,
+}
+
+/// The physical Shift-key position(s) and how engaging Shift is typed, so
+/// `penalty.rs` can charge the real cost of an uppercase `KeyPress`
+/// instead of letting it ride the free-to-reach upper layer. Unlike an
+/// `Activator`, which always engages one fixed position, a capital
+/// letter can be reached through either Shift key -- the penalty path
+/// favors whichever is opposite-hand from the letter (the standard
+/// touch-typing technique) and only charges a same-hand conflict when
+/// both Shift keys happen to share the letter's hand.
+#[derive(Clone, Copy)]
+pub struct ShiftKeys
+{
+	pub left_pos:  usize,
+	pub right_pos: usize,
+	pub mode:      ActivationMode,
+}
+
+/// A keyboard layout as a stack of layers reachable from the base layer
+/// (index 0) via `activators`. A layout with no activators behaves like
+/// the old lower/upper pair: every non-base layer is free to reach, which
+/// is what every preset layout below and `from_string` produce today.
 #[derive(Clone)]
-pub struct Layout(Layer, Layer);
+pub struct Layout
+{
+	layers:     Vec<Layer>,
+	activators: Vec<Activator>,
+	geometry:   KeyboardGeometry,
+	shift:      Option<ShiftKeys>,
+}
 
 pub struct LayoutPermutations
 {
 	orig_layout: Layout,
+	swappable: Vec<usize>,
 	swap_idx: Vec<usize>,
 	started: bool,
 }
 
-pub struct LayoutPosMap([Option<KeyPress>; 128]);
-
+/// Where a character lives on a layout: the physical `key` pressed, the
+/// `layer` it's on, and the sequence of base-layer activator positions
+/// (in press order) needed to reach that layer. `activation` is empty for
+/// the base layer, and also for any other layer with no registered
+/// `Activator` -- i.e. free to reach, preserving the behavior layouts
+/// without activators always had.
 #[derive(Clone)]
-pub struct LayoutShuffleMask(KeyMap<bool>);
+pub struct KeyLocation
+{
+	pub key:        KeyPress,
+	pub layer:      usize,
+	pub activation: Vec<usize>,
+}
+
+/// Maps a character to the `KeyLocation` that types it. Backed by a
+/// `HashMap` rather than a fixed-size table indexed by code point, since a
+/// layout built by `from_spec` can place any Unicode character, not just
+/// the first 128 code points `from_string`'s ASCII table was limited to.
+pub struct LayoutPosMap(HashMap<char, KeyLocation>);
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum Finger
@@ -83,191 +152,202 @@ pub struct KeyPress
 	pub hand:   Hand,
 	pub row:    Row,
 	pub center: bool,
+
+	/// The layer this key lives on; 0 for the base layer. Lets the
+	/// penalty path in `penalty.rs` tell a free keystroke from one that
+	/// first had to reach a non-base layer.
+	pub layer: usize,
+
+	/// The base-layer `Activator` position that switches to `layer`, if
+	/// any. `None` on the base layer, and also for any other layer with
+	/// no registered `Activator` -- i.e. free to reach, matching
+	/// `KeyLocation::activation`.
+	pub activator_pos: Option<usize>,
 }
 
 /* ------- *
  * STATICS *
  * ------- */
 
-pub static INIT_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', 'j', 'c', 'y', 'f', 'k',   'z', 'l', ',', 'u', 'q', '=',
-	              '\0', 'r', 's', 't', 'h', 'd',   'm', 'n', 'a', 'i', 'o','\'',
-	              '\0', '/', 'v', 'g', 'p', 'b',   'x', 'w', '.', ';', '-','\0',
-
-				  '\0', '\0', '\0', 'e', '\0',   '\0', ' ', '\0', '\0', '\0',
-				  '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', 'J', 'C', 'Y', 'F', 'K',   'Z', 'L', '<', 'U', 'Q', '+',
-	              '\0', 'R', 'S', 'T', 'H', 'D',   'M', 'N', 'A', 'I', 'O', '"',
-	              '\0', '?', 'V', 'G', 'P', 'B',   'X', 'W', '>', ':', '_','\0', 
-
-	              '\0', '\0', '\0', 'E', '\0', '\0', ' ', '\0', '\0', '\0',
-                  '\0', '\0', '\0', '\0'])));
+pub fn init_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', 'j', 'c', 'y', 'f', 'k',   'z', 'l', ',', 'u', 'q', '=',
+		              '\0', 'r', 's', 't', 'h', 'd',   'm', 'n', 'a', 'i', 'o','\'',
+		              '\0', '/', 'v', 'g', 'p', 'b',   'x', 'w', '.', ';', '-','\0',
+
+					  '\0', '\0', '\0', 'e', '\0',   '\0', ' ', '\0', '\0', '\0',
+					  '\0', '\0',   '\0', '\0'])),
+		Layer(KeyMap(['\0', 'J', 'C', 'Y', 'F', 'K',   'Z', 'L', '<', 'U', 'Q', '+',
+		              '\0', 'R', 'S', 'T', 'H', 'D',   'M', 'N', 'A', 'I', 'O', '"',
+		              '\0', '?', 'V', 'G', 'P', 'B',   'X', 'W', '>', ':', '_','\0',
+
+		              '\0', '\0', '\0', 'E', '\0', '\0', ' ', '\0', '\0', '\0',
+	                  '\0', '\0', '\0', '\0']))])
+}
 
-pub static QWERTY_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', 'q', 'w', 'e', 'r', 't',   'y', 'u', 'i', 'o', 'p', '-',
-	              '\0', 'a', 's', 'd', 'f', 'g',   'h', 'j', 'k', 'l', ';', '\'',
-	              '\0', 'z', 'x', 'c', 'v', 'b',   'n', 'm', ',', '.', '/','\0', 
+pub fn qwerty_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', 'q', 'w', 'e', 'r', 't',   'y', 'u', 'i', 'o', 'p', '-',
+		              '\0', 'a', 's', 'd', 'f', 'g',   'h', 'j', 'k', 'l', ';', '\'',
+		              '\0', 'z', 'x', 'c', 'v', 'b',   'n', 'm', ',', '.', '/','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', 'Q', 'W', 'E', 'R', 'T',   'Y', 'U', 'I', 'O', 'P', '_',
-	              '\0', 'A', 'S', 'D', 'F', 'G',   'H', 'J', 'K', 'L', ':', '"',
-	              '\0', 'Z', 'X', 'C', 'V', 'B',   'N', 'M', '<', '>', '?','\0', 
+		Layer(KeyMap(['\0', 'Q', 'W', 'E', 'R', 'T',   'Y', 'U', 'I', 'O', 'P', '_',
+		              '\0', 'A', 'S', 'D', 'F', 'G',   'H', 'J', 'K', 'L', ':', '"',
+		              '\0', 'Z', 'X', 'C', 'V', 'B',   'N', 'M', '<', '>', '?','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
+'\0', '\0',   '\0', '\0']))])
+}
 
-pub static DVORAK_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', '\'', ',', '.', 'p', 'y',   'f', 'g', 'c', 'r', 'l', '/',
-	              '\0', 'a', 'o', 'e', 'u', 'i',   'd', 'h', 't', 'n', 's', '-',
-	              '\0', ';', 'q', 'j', 'k', 'x',   'b', 'm', 'w', 'v', 'z','\0', 
+pub fn dvorak_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', '\'', ',', '.', 'p', 'y',   'f', 'g', 'c', 'r', 'l', '/',
+		              '\0', 'a', 'o', 'e', 'u', 'i',   'd', 'h', 't', 'n', 's', '-',
+		              '\0', ';', 'q', 'j', 'k', 'x',   'b', 'm', 'w', 'v', 'z','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', '"', ',', '.', 'P', 'Y',   'F', 'G', 'C', 'R', 'L', '?',
-	              '\0', 'A', 'O', 'E', 'U', 'I',   'D', 'H', 'T', 'N', 'S', '_',
-	              '\0', ':', 'Q', 'J', 'K', 'X',   'B', 'M', 'W', 'V', 'Z','\0', 
+		Layer(KeyMap(['\0', '"', ',', '.', 'P', 'Y',   'F', 'G', 'C', 'R', 'L', '?',
+		              '\0', 'A', 'O', 'E', 'U', 'I',   'D', 'H', 'T', 'N', 'S', '_',
+		              '\0', ':', 'Q', 'J', 'K', 'X',   'B', 'M', 'W', 'V', 'Z','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
+'\0', '\0',   '\0', '\0']))])
+}
 
-pub static COLEMAK_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', 'q', 'w', 'f', 'p', 'g',   'j', 'l', 'u', 'y', ';', '-',
-	              '\0', 'a', 'r', 's', 't', 'd',   'h', 'n', 'e', 'i', 'o', '\'',
-	              '\0', 'z', 'x', 'c', 'v', 'b',   'k', 'm', ',', '.', '/','\0', 
+pub fn colemak_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', 'q', 'w', 'f', 'p', 'g',   'j', 'l', 'u', 'y', ';', '-',
+		              '\0', 'a', 'r', 's', 't', 'd',   'h', 'n', 'e', 'i', 'o', '\'',
+		              '\0', 'z', 'x', 'c', 'v', 'b',   'k', 'm', ',', '.', '/','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', 'Q', 'W', 'F', 'P', 'G',   'J', 'L', 'U', 'Y', ':', '_',
-	              '\0', 'A', 'R', 'S', 'T', 'D',   'H', 'N', 'E', 'I', 'O', '"',
-	              '\0', 'Z', 'X', 'C', 'V', 'B',   'K', 'M', '<', '>', '?','\0', 
+		Layer(KeyMap(['\0', 'Q', 'W', 'F', 'P', 'G',   'J', 'L', 'U', 'Y', ':', '_',
+		              '\0', 'A', 'R', 'S', 'T', 'D',   'H', 'N', 'E', 'I', 'O', '"',
+		              '\0', 'Z', 'X', 'C', 'V', 'B',   'K', 'M', '<', '>', '?','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
+'\0', '\0',   '\0', '\0']))])
+}
 
-pub static QGMLWY_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', 'q', 'g', 'm', 'l', 'w',   'y', 'f', 'u', 'b', ';', '-',
-	              '\0', 'd', 's', 't', 'n', 'r',   'i', 'a', 'e', 'o', 'h', '\'',
-	              '\0', 'z', 'x', 'c', 'v', 'j',   'k', 'p', ',', '.', '/','\0', 
+pub fn qgmlwy_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', 'q', 'g', 'm', 'l', 'w',   'y', 'f', 'u', 'b', ';', '-',
+		              '\0', 'd', 's', 't', 'n', 'r',   'i', 'a', 'e', 'o', 'h', '\'',
+		              '\0', 'z', 'x', 'c', 'v', 'j',   'k', 'p', ',', '.', '/','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', 'Q', 'G', 'M', 'L', 'W',   'Y', 'F', 'U', 'B', ':', '_',
-	              '\0', 'D', 'S', 'T', 'N', 'R',   'I', 'A', 'E', 'O', 'H', '"',
-	              '\0', 'Z', 'X', 'C', 'V', 'J',   'K', 'P', '<', '>', '?','\0', 
+		Layer(KeyMap(['\0', 'Q', 'G', 'M', 'L', 'W',   'Y', 'F', 'U', 'B', ':', '_',
+		              '\0', 'D', 'S', 'T', 'N', 'R',   'I', 'A', 'E', 'O', 'H', '"',
+		              '\0', 'Z', 'X', 'C', 'V', 'J',   'K', 'P', '<', '>', '?','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
+'\0', '\0',   '\0', '\0']))])
+}
 
-pub static WORKMAN_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', 'q', 'd', 'r', 'w', 'b',   'j', 'f', 'u', 'p', ';', '-',
-	              '\0', 'a', 's', 'h', 't', 'g',   'y', 'n', 'e', 'o', 'i', '\'',
-	              '\0', 'z', 'x', 'm', 'c', 'v',   'k', 'l', ',', '.', '/','\0', 
+pub fn workman_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', 'q', 'd', 'r', 'w', 'b',   'j', 'f', 'u', 'p', ';', '-',
+		              '\0', 'a', 's', 'h', 't', 'g',   'y', 'n', 'e', 'o', 'i', '\'',
+		              '\0', 'z', 'x', 'm', 'c', 'v',   'k', 'l', ',', '.', '/','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', 'Q', 'D', 'R', 'W', 'B',   'J', 'F', 'U', 'P', ':', '_',
-	              '\0', 'A', 'S', 'H', 'T', 'G',   'Y', 'N', 'E', 'O', 'I', '"',
-	              '\0', 'Z', 'X', 'M', 'C', 'V',   'K', 'L', '<', '>', '?','\0', 
+		Layer(KeyMap(['\0', 'Q', 'D', 'R', 'W', 'B',   'J', 'F', 'U', 'P', ':', '_',
+		              '\0', 'A', 'S', 'H', 'T', 'G',   'Y', 'N', 'E', 'O', 'I', '"',
+		              '\0', 'Z', 'X', 'M', 'C', 'V',   'K', 'L', '<', '>', '?','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
+'\0', '\0',   '\0', '\0']))])
+}
 
-pub static MALTRON_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', 'q', 'p', 'y', 'c', 'b',   'v', 'm', 'u', 'z', 'l', '=',
-	              '\0', 'a', 'n', 'i', 's', 'f',   'd', 't', 'h', 'o', 'r', '\'',
-	              '\0', ',', '.', 'j', 'g', '/',   ';', 'w', 'k', '-', 'x','\0', 
+pub fn maltron_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', 'q', 'p', 'y', 'c', 'b',   'v', 'm', 'u', 'z', 'l', '=',
+		              '\0', 'a', 'n', 'i', 's', 'f',   'd', 't', 'h', 'o', 'r', '\'',
+		              '\0', ',', '.', 'j', 'g', '/',   ';', 'w', 'k', '-', 'x','\0',
 
 '\0', '\0', '\0', 'e', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', 'Q', 'P', 'Y', 'C', 'B',   'V', 'M', 'U', 'Z', 'L', '+',
-	              '\0', 'A', 'N', 'I', 'S', 'F',   'D', 'T', 'H', 'O', 'R', '"',
-	              '\0', '<', '>', 'J', 'G', '?',   ':', 'W', 'K', '_', 'X','\0', 
+		Layer(KeyMap(['\0', 'Q', 'P', 'Y', 'C', 'B',   'V', 'M', 'U', 'Z', 'L', '+',
+		              '\0', 'A', 'N', 'I', 'S', 'F',   'D', 'T', 'H', 'O', 'R', '"',
+		              '\0', '<', '>', 'J', 'G', '?',   ':', 'W', 'K', '_', 'X','\0',
 
 '\0', '\0', '\0', 'E', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
+'\0', '\0',   '\0', '\0']))])
+}
 
-pub static MTGAP_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', 'y', 'p', 'o', 'u', '-',   'b', 'd', 'l', 'c', 'k', 'j',
-	              '\0', 'i', 'n', 'e', 'a', ',',   'm', 'h', 't', 's', 'r', 'v',
-	              '\0', '(', '"', '\'', '.', '_',   ')', 'f', 'w', 'g', 'x','\0', 
+pub fn mtgap_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', 'y', 'p', 'o', 'u', '-',   'b', 'd', 'l', 'c', 'k', 'j',
+		              '\0', 'i', 'n', 'e', 'a', ',',   'm', 'h', 't', 's', 'r', 'v',
+		              '\0', '(', '"', '\'', '.', '_',   ')', 'f', 'w', 'g', 'x','\0',
 
 '\0', '\0', '\0', 'z', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', 'Y', 'P', 'O', 'U', ':',   'B', 'D', 'L', 'C', 'K', 'J',
-	              '\0', 'I', 'N', 'E', 'A', ';',   'M', 'H', 'T', 'S', 'R', 'V',
-	              '\0', '&', '?', '*', '=', '<',   '>', 'F', 'W', 'G', 'X','\0', 
+		Layer(KeyMap(['\0', 'Y', 'P', 'O', 'U', ':',   'B', 'D', 'L', 'C', 'K', 'J',
+		              '\0', 'I', 'N', 'E', 'A', ';',   'M', 'H', 'T', 'S', 'R', 'V',
+		              '\0', '&', '?', '*', '=', '<',   '>', 'F', 'W', 'G', 'X','\0',
 
 '\0', '\0', '\0', 'Z', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
+'\0', '\0',   '\0', '\0']))])
+}
 
-pub static CAPEWELL_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', '.', 'y', 'w', 'd', 'f',   'j', 'p', 'l', 'u', 'q', '/',
-	              '\0', 'a', 'e', 'r', 's', 'g',   'b', 't', 'n', 'i', 'o', '-',
-	              '\0', 'x', 'z', 'c', 'v', ';',   'k', 'w', 'h', ',', '\'','\0', 
+pub fn capewell_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', '.', 'y', 'w', 'd', 'f',   'j', 'p', 'l', 'u', 'q', '/',
+		              '\0', 'a', 'e', 'r', 's', 'g',   'b', 't', 'n', 'i', 'o', '-',
+		              '\0', 'x', 'z', 'c', 'v', ';',   'k', 'w', 'h', ',', '\'','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', '>', 'Y', 'W', 'D', 'F',   'J', 'P', 'L', 'U', 'Q', '?',
-	              '\0', 'A', 'E', 'R', 'S', 'G',   'B', 'T', 'N', 'I', 'O', '_',
-	              '\0', 'X', 'Z', 'C', 'V', ':',   'K', 'W', 'H', '<', '"','\0', 
+		Layer(KeyMap(['\0', '>', 'Y', 'W', 'D', 'F',   'J', 'P', 'L', 'U', 'Q', '?',
+		              '\0', 'A', 'E', 'R', 'S', 'G',   'B', 'T', 'N', 'I', 'O', '_',
+		              '\0', 'X', 'Z', 'C', 'V', ':',   'K', 'W', 'H', '<', '"','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
+'\0', '\0',   '\0', '\0']))])
+}
 
-pub static ARENSITO_LAYOUT: Layout = Layout(
-	Layer(KeyMap(['\0', 'q', 'l', ',', 'p', '\0',  '\0', 'f', 'u', 'd', 'k', '\0',
-	              '\0', 'a', 'r', 'e', 'n', 'b',   'g', 's', 'i', 't', 'o', '\0',
-	              '\0', 'z', 'w', '.', 'h', 'j',   'v', 'c', 'y', 'm', 'x','\0', 
+pub fn arensito_layout()
+-> Layout
+{
+	Layout::from_layers(vec![
+		Layer(KeyMap(['\0', 'q', 'l', ',', 'p', '\0',  '\0', 'f', 'u', 'd', 'k', '\0',
+		              '\0', 'a', 'r', 'e', 'n', 'b',   'g', 's', 'i', 't', 'o', '\0',
+		              '\0', 'z', 'w', '.', 'h', 'j',   'v', 'c', 'y', 'm', 'x','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
 '\0', '\0',   '\0', '\0'])),
-	Layer(KeyMap(['\0', 'Q', 'L', '<', 'P', '\0',  '\0', 'F', 'U', 'D', 'K', '\0',
-	              '\0', 'A', 'R', 'E', 'N', 'B',   'G', 'S', 'I', 'T', 'O', '\0',
-	              '\0', 'Z', 'W', '>', 'H', 'J',   'V', 'C', 'Y', 'M', 'X','\0', 
+		Layer(KeyMap(['\0', 'Q', 'L', '<', 'P', '\0',  '\0', 'F', 'U', 'D', 'K', '\0',
+		              '\0', 'A', 'R', 'E', 'N', 'B',   'G', 'S', 'I', 'T', 'O', '\0',
+		              '\0', 'Z', 'W', '>', 'H', 'J',   'V', 'C', 'Y', 'M', 'X','\0',
 
 '\0', '\0', '\0', '\0', '\0',   '\0', ' ', '\0', '\0', '\0',
-'\0', '\0',   '\0', '\0'])));
-
-// static LAYOUT_MASK: LayoutShuffleMask = LayoutShuffleMask(KeyMap([
-// 	true,  true,  true,  true,  true,  true,  true,  true,  true,  true,  false,
-// 	true,  true,  true,  true,  true,  true,  true,  true,  true,  true,  true,
-// 	true,  true,  true,  true,  true,  true,  true,  true,  true,  true,
-// 	false]));
-static LAYOUT_MASK_SWAP_OFFSETS: [usize; 49] = [
-	0, 0, 0, 0, 0, 0,    0, 0, 0, 0, 0,
-	1, 1, 1, 1, 1, 1,    1, 1, 1, 1, 1, 1,
-	1, 1, 1, 1, 1, 1,    1, 1, 1, 1, 1, 1,
-	   1, 1, 1, 1, 1,	 1, 1, 1, 1, 1,
-				1, 1,	 1, 1];
-static LAYOUT_MASK_NUM_SWAPPABLE: usize = 49;
-
-static KEY_FINGERS: KeyMap<Finger> = KeyMap([
-	Finger::Pinky, Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
-	Finger::Pinky, Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
-	Finger::Pinky, Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
-	Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb,
-	Finger::Thumb, Finger::Thumb, Finger::Thumb, Finger::Thumb]);
-static KEY_HANDS: KeyMap<Hand> = KeyMap([
-	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
-	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
-	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
-	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
-	Hand::Left, Hand::Left, Hand::Right, Hand::Right]);
-static KEY_ROWS: KeyMap<Row> = KeyMap([
-	Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,       Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,
-	Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,      Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,
-	Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,    Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,
-	Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome, Row::ThumbHome,
-	Row::ThumbTop, Row::ThumbTop, Row::ThumbTop, Row::ThumbTop]);
-static KEY_CENTER_COLUMN: KeyMap<bool> = KeyMap([
-	false, false, false, false, false, true,    true, false, false, false, false, false,
-	false, false, false, false, false, true,    true, false, false, false, false, false,
-	false, false, false, false, false, true,    true, false, false, false, false, false,
-		   false, false, false, false, false,   false, false, false, false, false,
-								false, false,   false, false]);
-
-pub static KP_NONE: Option<KeyPress> = None;
+'\0', '\0',   '\0', '\0']))])
+}
 
 static LAYOUT_FILE_IDXS: KeyMap<usize> = KeyMap([
 	0,  1,  2,  3,  4, 5,      6,  7,  8,  9,  10, 11,
@@ -276,66 +356,281 @@ static LAYOUT_FILE_IDXS: KeyMap<usize> = KeyMap([
 		38, 39, 40, 41, 42,    43, 44, 45, 46, 47,
 					48, 49,	   50, 51]);
 
+/// Blocks in the flat `from_string` format (one per layer) are separated by
+/// this marker -- a control character no layout assigns to a key.
+const LAYER_SEPARATOR: char = '\u{1}';
+
+/// Named keys recognized by `from_spec`, for positions whose output isn't a
+/// single printable character one would want to type literally in a spec
+/// file. Modeled on the named-key tables ergodox-style layout generators
+/// use for the same reason.
+const NAMED_KEYS: &'static [(&'static str, char)] = &[
+	("space", ' '),
+	("tab",    '\t'),
+	("return", '\n'),
+	("bspc",   '\u{8}'),
+	("esc",    '\u{1b}'),
+	("del",    '\u{7f}'),
+];
+
+/// Parses one `from_spec` key spec into the character it produces: a
+/// `NAMED_KEYS` name, a `\u{...}` Unicode escape, `_`/`none`/`dead` for an
+/// unassigned position (the same '\0' sentinel `from_block` leaves for a
+/// gap in the flat format), or a single literal character.
+fn parse_key_spec(spec: &str)
+-> Result<char, String>
+{
+	if spec == "_" || spec == "none" || spec == "dead" {
+		return Ok('\0');
+	}
+	if let Some(&(_, kc)) = NAMED_KEYS.iter().find(|&&(name, _)| name == spec) {
+		return Ok(kc);
+	}
+	if spec.starts_with("\\u{") && spec.ends_with('}') {
+		let hex = &spec[3..spec.len() - 1];
+		let code = u32::from_str_radix(hex, 16).map_err(|_| format!("bad unicode escape '{}'", spec))?;
+		return char::from_u32(code).ok_or_else(|| format!("'{}' is not a valid code point", spec));
+	}
+
+	let mut chars = spec.chars();
+	match (chars.next(), chars.next()) {
+		(Some(kc), None) => Ok(kc),
+		_ => Err(format!("'{}' is not a named key, \\u{{...}} escape, or single character", spec)),
+	}
+}
+
 /* ----- *
  * IMPLS *
  * ----- */
 
 impl Layout
 {
-	pub fn from_string(s: &str)
+	/// Builds a layout with no activators, i.e. every non-base layer is
+	/// free to reach -- the behavior every preset layout above, and
+	/// `from_string`, rely on. Uses the built-in ANSI `KeyboardGeometry`;
+	/// call `with_geometry` to retarget to a different physical board.
+	pub fn from_layers(layers: Vec<Layer>)
 	-> Layout
 	{
-		let s: Vec<char> = s.chars().collect();
-		let mut lower: [char; 50] = ['\0'; 50];
-		let mut upper: [char; 50] = ['\0'; 50];
+		Layout { layers: layers, activators: Vec::new(), geometry: KeyboardGeometry::ansi(), shift: None }
+	}
 
-		for i in 0..34 {
-			let file_i = LAYOUT_FILE_IDXS.0[i];
-			lower[i] = *s.get(file_i).unwrap_or(&'\0');
-			upper[i] = *s.get(file_i + 40/*todo: change to an offset > 50*/).unwrap_or(&'\0');
-		}
+	pub fn new(layers: Vec<Layer>, activators: Vec<Activator>)
+	-> Layout
+	{
+		Layout { layers: layers, activators: activators, geometry: KeyboardGeometry::ansi(), shift: None }
+	}
 
-		Layout(Layer(KeyMap(lower)), Layer(KeyMap(upper)))
+	/// Retargets this layout to a different physical `KeyboardGeometry`,
+	/// e.g. one loaded with `KeyboardGeometry::load` for a row-staggered
+	/// standard board or a split board with a different thumb cluster.
+	/// Pass the same `KeyboardGeometry` value to the scoring functions in
+	/// `penalty.rs` -- there's one board description now, not two that can
+	/// drift apart.
+	pub fn with_geometry(mut self, geometry: KeyboardGeometry)
+	-> Layout
+	{
+		self.geometry = geometry;
+		self
+	}
+
+	/// Equips this layout with explicit Shift keys, so the penalty path
+	/// charges a real keystroke (plus same-hand conflict, if any) for
+	/// every uppercase character instead of treating the upper layer as
+	/// free to reach. Without this, layouts behave as they always have.
+	pub fn with_shift(mut self, shift: ShiftKeys)
+	-> Layout
+	{
+		self.shift = Some(shift);
+		self
+	}
+
+	/// Parses `s` as `LAYER_SEPARATOR`-delimited keymap blocks, one per
+	/// layer, each in the same flat per-key-position format the original
+	/// single-layer format used. Doesn't express activators; use `new`
+	/// directly to build a layout with layer-switch keys.
+	pub fn from_string(s: &str)
+	-> Layout
+	{
+		let layers = s.split(LAYER_SEPARATOR).map(Layer::from_block).collect();
+		Layout::from_layers(layers)
+	}
+
+	/// Parses `s` as `LAYER_SEPARATOR`-delimited blocks of whitespace-
+	/// separated key specs, one per layer, one spec per `KeyMap` position
+	/// (50 per block, in the same left-to-right/row-by-row order the
+	/// `KeyMap format` comment above describes). Each spec is a named key
+	/// from `NAMED_KEYS` (`space`, `tab`, ...), a `\u{...}` Unicode escape,
+	/// `_`/`none`/`dead` for an unassigned position, or a single literal
+	/// character. Unlike `from_string`'s fixed-width ASCII table, this can
+	/// place any Unicode code point, which layouts for non-English text and
+	/// dedicated symbol layers need; it doesn't express activators, so use
+	/// `new` directly to build a layout with layer-switch keys.
+	pub fn from_spec(s: &str)
+	-> Result<Layout, String>
+	{
+		let layers: Result<Vec<Layer>, String> =
+			s.split(LAYER_SEPARATOR).map(Layer::from_spec_block).collect();
+		Ok(Layout::from_layers(layers?))
 	}
 
 	pub fn shuffle(&mut self, times: usize)
+	-> Result<(), String>
 	{
 		for _ in 0..times {
-			let (i, j) = Layout::shuffle_position();
-			let Layout(ref mut lower, ref mut upper) = *self;
-			lower.swap(i, j);
-			upper.swap(i, j);
+			self.shuffle_swap()?;
+		}
+		Ok(())
+	}
+
+	/// Swaps one randomly chosen swappable position pair across every
+	/// layer, like a single step of `shuffle`, and returns the positions
+	/// swapped so a caller tracking per-position state alongside the
+	/// layout (e.g. an `IncrementalScorer`) can mirror the change instead
+	/// of recomputing from scratch. Errs if there aren't at least two
+	/// swappable positions to pick a pair from -- see `shuffle_position`.
+	pub fn shuffle_swap(&mut self)
+	-> Result<(usize, usize), String>
+	{
+		let (i, j) = self.shuffle_position()?;
+		for layer in self.layers.iter_mut() {
+			layer.swap(i, j);
 		}
+		Ok((i, j))
 	}
 
 	pub fn get_position_map(&self)
 	-> LayoutPosMap
 	{
-		let Layout(ref lower, ref upper) = *self;
-		let mut map = [None; 128];
-		lower.fill_position_map(&mut map);
-		upper.fill_position_map(&mut map);
+		let mut map: HashMap<char, KeyLocation> = HashMap::new();
+		for (layer_idx, layer) in self.layers.iter().enumerate() {
+			let activation = self.activation_sequence(layer_idx);
+			layer.fill_position_map(&mut map, layer_idx, &activation, &self.geometry);
+		}
 
 		LayoutPosMap(map)
 	}
 
-	fn shuffle_position()
-	-> (usize, usize)
+	/// The lowercase character assigned to `pos` on the base layer. Unlike
+	/// `get_position_map`, this goes position -> char rather than char ->
+	/// position, which is what a caller tracking "what's at position N"
+	/// (e.g. an incremental scorer mirroring a swap) needs.
+	pub fn get_char(&self, pos: usize)
+	-> char
+	{
+		let Layer(KeyMap(ref layer)) = self.layers[0];
+		layer[pos]
+	}
+
+	/// This layout's activators, so `penalty.rs` can price the
+	/// layer-switch cost of the `activator_pos` it finds on a `KeyPress`
+	/// without needing the whole `Layout`.
+	pub(crate) fn activators(&self)
+	-> &[Activator]
+	{
+		&self.activators
+	}
+
+	/// This layout's Shift-key configuration, if any, so `penalty.rs` can
+	/// price an uppercase `KeyPress` without needing the whole `Layout`.
+	pub(crate) fn shift(&self)
+	-> Option<ShiftKeys>
+	{
+		self.shift
+	}
+
+	/// The activator key positions, in press order, needed to reach
+	/// `layer_idx`. Empty for the base layer and for any layer with no
+	/// registered `Activator`.
+	fn activation_sequence(&self, layer_idx: usize)
+	-> Vec<usize>
+	{
+		if layer_idx == 0 {
+			return Vec::new();
+		}
+		match self.activators.iter().find(|a| a.target_layer == layer_idx) {
+			Some(activator) => vec![activator.pos],
+			None => Vec::new(),
+		}
+	}
+
+	/// Every swappable position, i.e. the positions this layout's
+	/// `KeyboardGeometry` marks swappable minus this layout's own
+	/// activator positions and Shift positions, so shuffling and
+	/// permuting never move a layer switch, a fixed-function Shift key,
+	/// or a key the geometry pins (e.g. the home row).
+	fn swappable_positions(&self)
+	-> Vec<usize>
+	{
+		let activator_positions: Vec<usize> = self.activators.iter().map(|a| a.pos).collect();
+		let shift_positions: Vec<usize> =
+			self.shift.as_ref().map_or(vec![], |s| vec![s.left_pos, s.right_pos]);
+		self.geometry.swappable_positions().into_iter()
+			.filter(|pos| !activator_positions.contains(pos))
+			.filter(|pos| !shift_positions.contains(pos))
+			.collect()
+	}
+
+	/// Picks two distinct swappable positions to swap. Errs instead of
+	/// dividing by zero/underflowing when the profile, activators, and
+	/// Shift positions combine (chunk2-3/chunk2-1/chunk2-5) to leave
+	/// fewer than 2 swappable positions -- e.g. a small custom board or a
+	/// profile that pins most keys non-swappable.
+	fn shuffle_position(&self)
+	-> Result<(usize, usize), String>
 	{
-		let mut i = random::<usize>() % LAYOUT_MASK_NUM_SWAPPABLE;
-		let mut j = random::<usize>() % (LAYOUT_MASK_NUM_SWAPPABLE - 1);
+		let swappable = self.swappable_positions();
+		let num_swappable = swappable.len();
+		if num_swappable < 2 {
+			return Err(format!(
+				"not enough swappable positions to shuffle: need at least 2, have {}",
+				num_swappable));
+		}
+
+		let i = random::<usize>() % num_swappable;
+		let mut j = random::<usize>() % (num_swappable - 1);
 		if j >= i {
 			j += 1;
 		}
-		i += LAYOUT_MASK_SWAP_OFFSETS[i];
-		j += LAYOUT_MASK_SWAP_OFFSETS[j];
 
-		(i, j)
+		Ok((swappable[i], swappable[j]))
 	}
 }
 
 impl Layer
 {
+	fn from_block(s: &str)
+	-> Layer
+	{
+		let s: Vec<char> = s.chars().collect();
+		let mut chars: [char; 50] = ['\0'; 50];
+
+		for i in 0..34 {
+			let file_i = LAYOUT_FILE_IDXS.0[i];
+			chars[i] = *s.get(file_i).unwrap_or(&'\0');
+		}
+
+		Layer(KeyMap(chars))
+	}
+
+	/// Parses one `from_spec` block: exactly 50 whitespace-separated key
+	/// specs, in `KeyMap` position order.
+	fn from_spec_block(s: &str)
+	-> Result<Layer, String>
+	{
+		let specs: Vec<&str> = s.split_whitespace().collect();
+		if specs.len() != 50 {
+			return Err(format!("expected 50 key specs, got {}", specs.len()));
+		}
+
+		let mut chars: [char; 50] = ['\0'; 50];
+		for (i, spec) in specs.iter().enumerate() {
+			chars[i] = parse_key_spec(spec)?;
+		}
+
+		Ok(Layer(KeyMap(chars)))
+	}
+
 	fn swap(&mut self, i: usize, j: usize)
 	{
 		let Layer(KeyMap(ref mut layer)) = *self;
@@ -344,22 +639,25 @@ impl Layer
 		layer[j] = temp;
 	}
 
-	fn fill_position_map(&self, map: &mut [Option<KeyPress>; 128])
+	fn fill_position_map(&self, map: &mut HashMap<char, KeyLocation>, layer_idx: usize, activation: &[usize], geometry: &KeyboardGeometry)
 	{
 		let Layer(KeyMap(ref layer)) = *self;
-		let KeyMap(ref fingers) = KEY_FINGERS;
-		let KeyMap(ref hands) = KEY_HANDS;
-		let KeyMap(ref rows) = KEY_ROWS;
-		let KeyMap(ref centers) = KEY_CENTER_COLUMN;
 		for (i, c) in layer.into_iter().enumerate() {
-			if *c < (128 as char) {
-				map[*c as usize] = Some(KeyPress {
-					kc: *c,
-					pos: i,
-					finger: fingers[i],
-					hand: hands[i],
-					row: rows[i],
-					center: centers[i],
+			if *c != '\0' {
+				let key = geometry.key(i);
+				map.insert(*c, KeyLocation {
+					key: KeyPress {
+						kc: *c,
+						pos: i,
+						finger: key.finger,
+						hand: key.hand,
+						row: key.row,
+						center: key.center,
+						layer: layer_idx,
+						activator_pos: activation.last().cloned(),
+					},
+					layer: layer_idx,
+					activation: activation.to_vec(),
 				});
 			}
 		}
@@ -368,15 +666,25 @@ impl Layer
 
 impl LayoutPosMap
 {
+	/// The full `KeyLocation` -- physical key, layer, and activation path
+	/// -- for `kc`, by reference so a lookup doesn't clone the owned
+	/// `activation` path. Most callers only need the `KeyPress` itself;
+	/// see `get_key`.
 	pub fn get_key_position(&self, kc: char)
-	-> &Option<KeyPress>
+	-> Option<&KeyLocation>
 	{
 		let LayoutPosMap(ref map) = *self;
-		if kc < (128 as char) {
-			&map[kc as usize]
-		} else {
-			&KP_NONE
-		}
+		map.get(&kc)
+	}
+
+	/// The `KeyPress` at `kc`'s position, discarding the full
+	/// `KeyLocation` activation path. Cheap -- `KeyPress` is `Copy` -- so
+	/// this is what the per-character penalty loops that don't need the
+	/// activation path should call instead of `get_key_position`.
+	pub fn get_key(&self, kc: char)
+	-> Option<KeyPress>
+	{
+		self.get_key_position(kc).map(|loc| loc.key)
 	}
 }
 
@@ -391,6 +699,7 @@ impl LayoutPermutations
 		}
 		LayoutPermutations {
 			orig_layout: layout.clone(),
+			swappable: layout.swappable_positions(),
 			swap_idx: swaps,
 			started: false,
 		}
@@ -404,13 +713,14 @@ impl Iterator for LayoutPermutations
 	fn next(&mut self)
 	-> Option<Layout>
 	{
+		let num_swappable = self.swappable.len();
 		let mut some = false;
 		let mut idx = 0;
 		let mut val = 0;
 
 		if self.started {
 			for (i, e) in self.swap_idx.iter_mut().enumerate() {
-				if *e + 1 < LAYOUT_MASK_NUM_SWAPPABLE - i {
+				if *e + 1 < num_swappable - i {
 					*e += 1;
 					some = true;
 					idx = i;
@@ -433,12 +743,11 @@ impl Iterator for LayoutPermutations
 			let mut layout = self.orig_layout.clone();
 			let mut i = 0;
 			while i < self.swap_idx.len() {
-				let ref mut lower = ((layout.0).0).0;
-				let ref mut upper = ((layout.1).0).0;
-				let swap_left = self.swap_idx[i] + LAYOUT_MASK_SWAP_OFFSETS[self.swap_idx[i]];
-				let swap_right = self.swap_idx[i + 1] + LAYOUT_MASK_SWAP_OFFSETS[self.swap_idx[i + 1]];
-				lower.swap(swap_left, swap_right);
-				upper.swap(swap_left, swap_right);
+				let swap_left = self.swappable[self.swap_idx[i]];
+				let swap_right = self.swappable[self.swap_idx[i + 1]];
+				for layer in layout.layers.iter_mut() {
+					layer.swap(swap_left, swap_right);
+				}
 				i += 2;
 			}
 
@@ -454,8 +763,7 @@ impl fmt::Display for Layout
 	fn fmt(&self, f: &mut fmt::Formatter)
 	-> fmt::Result
 	{
-		let Layout(ref lower, _) = *self;
-		lower.fmt(f)
+		self.layers[0].fmt(f)
 	}
 }
 
@@ -2,7 +2,6 @@
 /// corpus string.
 
 use std::vec::Vec;
-use std::ops::Range;
 use std::collections::HashMap;
 use std::fmt;
 
@@ -12,7 +11,13 @@ use layout::KeyMap;
 use layout::KeyPress;
 use layout::Finger;
 use layout::Row;
-use layout::KP_NONE;
+use layout::ActivationMode;
+use layout::ShiftKeys;
+use geometry::KeyboardGeometry;
+use penalty_table::PenaltyTable;
+use penalty_table::PenaltyTableBuilder;
+use penalty_table::PENALTY_TABLE_SIZE;
+use roll;
 
 pub struct KeyPenalty<'a>
 {
@@ -29,6 +34,156 @@ pub struct KeyPenaltyResult<'a>
 
 pub struct QuartadList<'a>(HashMap<&'a str, usize>);
 
+/// The same-finger, stretch, and same-key bigram penalties for a single
+/// scoring profile, precomputed once into lookup tables instead of being
+/// recomputed from geometry on every quartad. Built from a
+/// `KeyboardGeometry` at startup; the geometry's distance formulas
+/// become the default table, and a table file can still override
+/// individual entries afterwards.
+pub struct PenaltyTables
+{
+    pub same_finger:     PenaltyTable,
+    pub stretch:         PenaltyTable,
+    pub same_key_pinky:  f64,
+    pub(crate) same_finger_coeff:   f64,
+    pub(crate) stretch_coeff:       f64,
+    pub(crate) long_jump_surcharge: f64,
+}
+
+impl PenaltyTables
+{
+    pub fn generate(geometry: &KeyboardGeometry, same_finger_coeff: f64, stretch_coeff: f64, same_key_pinky: f64, long_jump_surcharge: f64)
+    -> PenaltyTables
+    {
+        let mut same_finger = PenaltyTableBuilder::new();
+        let mut stretch = PenaltyTableBuilder::new();
+
+        for old1 in 0..PENALTY_TABLE_SIZE {
+            for curr in 0..PENALTY_TABLE_SIZE {
+                if old1 == curr {
+                    continue;
+                }
+                same_finger.set(old1, curr, same_finger_distance_penalty(old1, curr, geometry, same_finger_coeff, long_jump_surcharge));
+                stretch.set(old1, curr, geometry.distance(old1, curr) * stretch_coeff);
+            }
+        }
+
+        PenaltyTables {
+            same_finger: same_finger.build(),
+            stretch: stretch.build(),
+            same_key_pinky: same_key_pinky,
+            same_finger_coeff: same_finger_coeff,
+            stretch_coeff: stretch_coeff,
+            long_jump_surcharge: long_jump_surcharge,
+        }
+    }
+
+    /// The "comfort" profile: favors ergonomics over raw speed, using
+    /// the same coefficients the distance formulas originally shipped
+    /// with.
+    pub fn comfort(geometry: &KeyboardGeometry)
+    -> PenaltyTables
+    {
+        PenaltyTables::generate(geometry, COMFORT_SAME_FINGER_COEFF, COMFORT_STRETCH_COEFF, COMFORT_SAME_KEY_PINKY, COMFORT_LONG_JUMP_SURCHARGE)
+    }
+
+    /// The "speed" profile: weighs raw travel distance more heavily and
+    /// forgives awkward finger/stretch combinations that a fast typist
+    /// powers through anyway.
+    pub fn speed(geometry: &KeyboardGeometry)
+    -> PenaltyTables
+    {
+        PenaltyTables::generate(geometry, SPEED_SAME_FINGER_COEFF, SPEED_STRETCH_COEFF, SPEED_SAME_KEY_PINKY, SPEED_LONG_JUMP_SURCHARGE)
+    }
+}
+
+/// Blends a `comfort` and a `speed` penalty profile by a `phase` in
+/// `[0.0, 1.0]`, the same tapered-evaluation technique chess engines use
+/// to interpolate between midgame and endgame parameter sets:
+/// `phase*speed + (1.0-phase)*comfort`. A `phase` of 0.0 scores purely
+/// for comfort, 1.0 purely for speed.
+pub struct TaperedPenaltyTables
+{
+    pub comfort: PenaltyTables,
+    pub speed:   PenaltyTables,
+    pub phase:   f64,
+
+    // The fixed bigram/trigram/quadgram penalty magnitudes `penalize`
+    // used to have hardcoded as literals. They don't (yet) vary between
+    // the comfort and speed profiles, but living here means they are
+    // already threaded everywhere `tables` is, which is what `tune.rs`
+    // needs to fit them to ranked layout data instead of hand-picking
+    // them.
+    pub roll_out:              f64,
+    pub roll_in:               f64,
+    pub roll_reversal:         f64,
+    pub twist:                 f64,
+    pub pinky_ring_alternation: f64,
+    pub same_hand:             f64,
+    pub alternating_hand:      f64,
+    pub long_jump_sandwich:    f64,
+    pub activator_keystroke:   f64,
+    pub shift_keystroke:       f64,
+    pub center_stretch_surcharge: f64,
+}
+
+impl TaperedPenaltyTables
+{
+    pub fn new(geometry: &KeyboardGeometry, phase: f64)
+    -> TaperedPenaltyTables
+    {
+        assert!(phase >= 0.0 && phase <= 1.0, "phase must be in [0.0, 1.0]");
+        TaperedPenaltyTables {
+            comfort: PenaltyTables::comfort(geometry),
+            speed: PenaltyTables::speed(geometry),
+            phase: phase,
+            roll_out:               ROLL_OUT_PENALTY,
+            roll_in:                ROLL_IN_BONUS,
+            roll_reversal:          ROLL_REVERSAL_PENALTY,
+            twist:                  TWIST_PENALTY,
+            pinky_ring_alternation: PINKY_RING_ALTERNATION_PENALTY,
+            same_hand:              SAME_HAND_PENALTY,
+            alternating_hand:       ALTERNATING_HAND_PENALTY,
+            long_jump_sandwich:     LONG_JUMP_SANDWICH_PENALTY,
+            activator_keystroke:    ACTIVATOR_KEYSTROKE_PENALTY,
+            shift_keystroke:        SHIFT_KEYSTROKE_PENALTY,
+            center_stretch_surcharge: CENTER_STRETCH_SURCHARGE,
+        }
+    }
+
+    fn blend(&self, comfort: f64, speed: f64)
+    -> f64
+    {
+        self.phase * speed + (1.0 - self.phase) * comfort
+    }
+
+    pub fn same_finger(&self, old1: usize, curr: usize, geometry: &KeyboardGeometry)
+    -> f64
+    {
+        let comfort = self.comfort.same_finger.get(old1, curr)
+            .unwrap_or_else(|| same_finger_distance_penalty(old1, curr, geometry, self.comfort.same_finger_coeff, self.comfort.long_jump_surcharge));
+        let speed = self.speed.same_finger.get(old1, curr)
+            .unwrap_or_else(|| same_finger_distance_penalty(old1, curr, geometry, self.speed.same_finger_coeff, self.speed.long_jump_surcharge));
+        self.blend(comfort, speed)
+    }
+
+    pub fn stretch(&self, old1: usize, curr: usize, geometry: &KeyboardGeometry)
+    -> f64
+    {
+        let comfort = self.comfort.stretch.get(old1, curr)
+            .unwrap_or_else(|| geometry.distance(old1, curr) * self.comfort.stretch_coeff);
+        let speed = self.speed.stretch.get(old1, curr)
+            .unwrap_or_else(|| geometry.distance(old1, curr) * self.speed.stretch_coeff);
+        self.blend(comfort, speed)
+    }
+
+    pub fn same_key_pinky(&self)
+    -> f64
+    {
+        self.blend(self.comfort.same_key_pinky, self.speed.same_key_pinky)
+    }
+}
+
 impl <'a> fmt::Display for KeyPenaltyResult<'a>
 {
     fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -48,6 +203,57 @@ static BASE_PENALTY: KeyMap<f64> = KeyMap([
     3.00, 2.00, 1.50, 1.00, 2.00,    2.00, 1.00, 1.50, 2.00, 3.00,
                             0.00,    0.00]);
 
+// Coefficients for the distance-driven same-finger/stretch penalties in
+// `calculate_same_finger_penalty`/`calculate_stretch_penalty`, one set
+// per tapered scoring profile (see `TaperedPenaltyTables`). The comfort
+// values are chosen so that a one-key-width reach on the ANSI profile
+// lands in roughly the same range as the position-pair penalties they
+// replace; the speed values weigh raw distance less and same-finger
+// awkwardness less, since a fast typist tolerates both more readily.
+static COMFORT_SAME_FINGER_COEFF: f64 = 5.0;
+static COMFORT_STRETCH_COEFF:     f64 = 1.0;
+static COMFORT_SAME_KEY_PINKY:    f64 = 3.0;
+static SPEED_SAME_FINGER_COEFF:   f64 = 2.5;
+static SPEED_STRETCH_COEFF:       f64 = 0.5;
+static SPEED_SAME_KEY_PINKY:      f64 = 1.0;
+
+// The extra surcharge `same_finger_distance_penalty` adds on top of
+// distance for a same-finger top-to-bottom-row (or bottom-to-top) jump,
+// one per tapered scoring profile so `tune` can fit it like every other
+// penalty magnitude instead of it being baked in identically for both.
+static COMFORT_LONG_JUMP_SURCHARGE: f64 = 15.0;
+static SPEED_LONG_JUMP_SURCHARGE:   f64 = 15.0;
+
+// Default magnitudes for the fixed bigram/trigram/quadgram penalties in
+// `penalize`, carried as defaults on `TaperedPenaltyTables` so `tune.rs`
+// can fit them to ranked layout data instead of hand-picking them.
+static ROLL_OUT_PENALTY:               f64 = 0.125;
+static ROLL_IN_BONUS:                  f64 = 0.125;
+static ROLL_REVERSAL_PENALTY:          f64 = 20.0;
+static TWIST_PENALTY:                  f64 = 10.0;
+static PINKY_RING_ALTERNATION_PENALTY: f64 = 15.0;
+static SAME_HAND_PENALTY:              f64 = 0.1;
+static ALTERNATING_HAND_PENALTY:       f64 = 0.5;
+static LONG_JUMP_SANDWICH_PENALTY:     f64 = 3.0;
+
+// The flat added cost of engaging a layer's `Activator` -- the hold (for
+// `Momentary`) or extra tap (for `Sticky`) before the target key can be
+// pressed -- on top of whatever same-hand/same-finger conflict it has
+// with the key it reaches. See `calculate_layer_switch_penalty`.
+static ACTIVATOR_KEYSTROKE_PENALTY:    f64 = 2.0;
+
+// The flat added cost of an uppercase `KeyPress` -- engaging whichever
+// Shift key the penalty path picks -- on top of any same-hand conflict it
+// has with the letter. See `calculate_shift_penalty`.
+static SHIFT_KEYSTROKE_PENALTY:        f64 = 2.0;
+
+// The extra surcharge `calculate_stretch_penalty` adds when either key of
+// a stretch involves a `KeyboardGeometry`'s center-column position, on top
+// of the geometric distance: reaching into the center column crosses into
+// the other hand's natural territory, which is awkward in a way raw
+// distance alone doesn't capture. See `KeyGeometry::center`.
+static CENTER_STRETCH_SURCHARGE:       f64 = 0.5;
+
 pub fn init<'a>()
 -> Vec<KeyPenalty<'a>>
 {
@@ -130,29 +336,71 @@ pub fn init<'a>()
         name: "same key",
     });
 
+    // 12. Penalize engaging a non-base-layer's activator -- the
+    // keystroke cost of the hold/tap itself, plus a conflict penalty if
+    // the activator shares a hand or finger with the key it reaches.
+    // Zero for every base-layer character and for layers with no
+    // registered `Activator`, so single-layer layouts score unchanged.
+    penalties.push(KeyPenalty {
+        name: "layer switch",
+    });
+
+    // 13. Penalize typing an uppercase character with the real cost of
+    // pressing Shift: the keystroke itself, plus a conflict penalty if
+    // the Shift key the penalty path picks shares a hand with the
+    // letter. Zero for every lowercase character and for layouts with no
+    // `ShiftKeys` configured, so existing behavior is unchanged.
+    penalties.push(KeyPenalty {
+        name: "shift",
+    });
+
+    // 14. Discount sustained 3+ key same-hand roll runs, growing with run
+    // length, and penalize a run that immediately reverses direction on
+    // the same hand. The quartad-by-quartad penalties above only ever see
+    // a 4-key window and so can't tell a roll run from a lone roll-in/out
+    // pair; this walks the whole corpus via `RollAnalyzer` instead. See
+    // `roll.rs`.
+    penalties.push(KeyPenalty {
+        name: "roll run",
+    });
+
     penalties
 }
 
-pub fn prepare_quartad_list<'a>(
+/// `position_map` only gets consulted while building the result -- the
+/// returned `QuartadList` borrows slices of `string` alone -- so it takes
+/// its own lifetime `'b` instead of being tied to `'a`. That lets a
+/// caller build `position_map` in a short-lived scope (e.g. per judgment
+/// layout in `tune.rs`) while still holding onto the `QuartadList` for
+/// as long as `string` lives.
+pub fn prepare_quartad_list<'a, 'b>(
     string:       &'a str,
-    position_map: &'a LayoutPosMap)
+    position_map: &'b LayoutPosMap)
 -> QuartadList<'a>
 {
-    let mut range: Range<usize> = 0..0;
     let mut quartads: HashMap<&str, usize> = HashMap::new();
-    for (i, c) in string.chars().enumerate() {
-        match *position_map.get_key_position(c) {
+
+    // Byte offsets of the up-to-4 trailing mapped chars, oldest first, so
+    // the quartad slice below always lands on char boundaries -- `string`
+    // can hold arbitrary Unicode via `from_spec`, so a byte-offset window
+    // (the old `Range<usize>` built from char indices) would slice mid
+    // codepoint the moment a multi-byte char shows up.
+    let mut window: Vec<usize> = Vec::new();
+
+    for (byte_start, c) in string.char_indices() {
+        match position_map.get_key(c) {
             Some(_) => {
-                range.end = i + 1;
-                if range.end > 3 && range.start < range.end - 4 {
-                    range.start = range.end - 4;
+                window.push(byte_start);
+                if window.len() > 4 {
+                    window.remove(0);
                 }
-                let quartad = &string[range.clone()];
+
+                let quartad = &string[window[0]..(byte_start + c.len_utf8())];
                 let entry = quartads.entry(quartad).or_insert(0);
                 *entry += 1;
             },
             None => {
-                range = (i + 1)..(i + 1);
+                window.clear();
             }
         }
     }
@@ -161,10 +409,13 @@ pub fn prepare_quartad_list<'a>(
 }
 
 pub fn calculate_penalty<'a>(
+    corpus:        &   str,
     quartads:  &   QuartadList<'a>,
     len:           usize,
     layout:    &   Layout,
     penalties: &'a Vec<KeyPenalty>,
+    geometry:  &   KeyboardGeometry,
+    tables:    &   TaperedPenaltyTables,
     detailed:      bool)
 -> (f64, f64, Vec<KeyPenaltyResult<'a>>)
 {
@@ -182,10 +433,24 @@ pub fn calculate_penalty<'a>(
         }
     }
 
+    let activator_modes: HashMap<usize, ActivationMode> =
+        layout.activators().iter().map(|a| (a.pos, a.mode)).collect();
+    let shift = layout.shift();
+
     let position_map = layout.get_position_map();
     for (string, count) in quartads {
-        total += penalty_for_quartad(string, *count, &position_map, &mut result, detailed);
+        total += penalty_for_quartad(
+            string, *count, &position_map, geometry, tables, &activator_modes, shift, &mut result, detailed);
+    }
+
+    // 14: Roll run, over the whole corpus rather than per-quartad -- see
+    // `init`.
+    let roll_adjustment = roll::RollAnalyzer::default_analyzer().analyze(corpus, &position_map);
+    if detailed {
+        let roll_idx = result.len() - 1;
+        result[roll_idx].total += roll_adjustment;
     }
+    total += roll_adjustment;
 
     (total, total / (len as f64), result)
 }
@@ -194,6 +459,10 @@ fn penalty_for_quartad<'a, 'b>(
     string:       &'a str,
     count:            usize,
     position_map: &'b LayoutPosMap,
+    geometry:     &'b KeyboardGeometry,
+    tables:       &'b TaperedPenaltyTables,
+    activator_modes: &'b HashMap<usize, ActivationMode>,
+    shift:            Option<ShiftKeys>,
     result:       &'b mut Vec<KeyPenaltyResult<'a>>,
     detailed:         bool)
 -> f64
@@ -204,27 +473,29 @@ fn penalty_for_quartad<'a, 'b>(
     let opt_old2 = chars.next();
     let opt_old3 = chars.next();
 
-    let curr = match opt_curr {
-        Some(c) => match position_map.get_key_position(c) {
-            &Some(ref kp) => kp,
-            &None => { return 0.0 }
-        },
+    let curr_c = match opt_curr {
+        Some(c) => c,
         None => panic!("unreachable")
     };
-    let old1 = match opt_old1 {
-        Some(c) => position_map.get_key_position(c),
-        None => &KP_NONE
-    };
-    let old2 = match opt_old2 {
-        Some(c) => position_map.get_key_position(c),
-        None => &KP_NONE
-    };
-    let old3 = match opt_old3 {
-        Some(c) => position_map.get_key_position(c),
-        None => &KP_NONE
+    let curr = match key_press_at(position_map, curr_c) {
+        Some(kp) => kp,
+        None => { return 0.0 }
     };
+    let old1 = opt_old1.and_then(|c| key_press_at(position_map, c));
+    let old2 = opt_old2.and_then(|c| key_press_at(position_map, c));
+    let old3 = opt_old3.and_then(|c| key_press_at(position_map, c));
 
-    penalize(string, count, &curr, old1, old2, old3, result, detailed)
+    penalize(string, count, &curr, &old1, &old2, &old3, geometry, tables, activator_modes, shift, result, detailed)
+}
+
+/// The `KeyPress` at `c`'s position, discarding the full `KeyLocation`
+/// activation path `LayoutPosMap` tracks -- the quartad penalties below
+/// only need the physical key itself, which already carries its own
+/// `layer`/`activator_pos` for the layer-switch penalty.
+fn key_press_at(position_map: &LayoutPosMap, c: char)
+-> Option<KeyPress>
+{
+    position_map.get_key(c)
 }
 
 fn penalize<'a, 'b>(
@@ -234,16 +505,31 @@ fn penalize<'a, 'b>(
     old1:   &       Option<KeyPress>,
     old2:   &       Option<KeyPress>,
     old3:   &       Option<KeyPress>,
+    geometry: &'b   KeyboardGeometry,
+    tables: &'b     TaperedPenaltyTables,
+    activator_modes: &'b HashMap<usize, ActivationMode>,
+    shift:              Option<ShiftKeys>,
     result: &'b mut Vec<KeyPenaltyResult<'a>>,
     detailed:       bool)
 -> f64
 {
-    let len = string.len();
     let count = count as f64;
     let mut total = 0.0;
 
+    // Byte offsets where each of the last 1..4 chars of `string` starts,
+    // so `slice1..slice4` below land on char boundaries instead of
+    // `string.len() - n` bytes back -- `string` can hold multi-byte
+    // Unicode via `from_spec`, and this quartad is already known to be at
+    // most 4 chars long (see `prepare_quartad_list`).
+    let end = string.len();
+    let char_starts: Vec<usize> = string.char_indices().map(|(i, _)| i).collect();
+    let tail_start = |n: usize| -> usize {
+        let num_chars = char_starts.len();
+        if n >= num_chars { 0 } else { char_starts[num_chars - n] }
+    };
+
     // One key penalties.
-    let slice1 = &string[(len - 1)..len];
+    let slice1 = &string[tail_start(1)..end];
 
     // 0: Base penalty.
     let base = BASE_PENALTY.0[curr.pos] * count;
@@ -253,6 +539,26 @@ fn penalize<'a, 'b>(
     }
     total += base;
 
+    // 12: Layer switch.
+    if let Some(activator_pos) = curr.activator_pos {
+        let penalty = calculate_layer_switch_penalty(curr, activator_pos, old1, geometry, tables, activator_modes) * count;
+        if detailed && penalty != 0. {
+            *result[12].high_keys.entry(slice1).or_insert(0.0) += penalty;
+            result[12].total += penalty;
+        }
+        total += penalty;
+    }
+
+    // 13: Shift.
+    if let Some(ref shift) = shift {
+        let penalty = calculate_shift_penalty(curr, old1, shift, geometry, tables) * count;
+        if detailed && penalty != 0. {
+            *result[13].high_keys.entry(slice1).or_insert(0.0) += penalty;
+            result[13].total += penalty;
+        }
+        total += penalty;
+    }
+
     // Two key penalties.
     let old1 = match *old1 {
         Some(ref o) => o,
@@ -260,11 +566,11 @@ fn penalize<'a, 'b>(
     };
 
     if curr.hand == old1.hand {
-        let slice2 = &string[(len - 2)..len];
+        let slice2 = &string[tail_start(2)..end];
 
         // 2: Same finger.
         if curr.finger == old1.finger && curr.pos != old1.pos {
-            let penalty = calculate_same_finger_penalty(curr, old1);
+            let penalty = calculate_same_finger_penalty(curr, old1, geometry, tables);
             let penalty = penalty * count;
             if detailed && penalty > 0. {
                 *result[2].high_keys.entry(slice2).or_insert(0.0) += penalty;
@@ -275,7 +581,7 @@ fn penalize<'a, 'b>(
 
         // 3: Stretch.
         if curr.finger != old1.finger {
-            let penalty = calculate_stretch_penalty(curr, old1);
+            let penalty = calculate_stretch_penalty(curr, old1, geometry, tables);
             let penalty = penalty * count;
             if detailed && penalty > 0. {
                 *result[3].high_keys.entry(slice2).or_insert(0.0) += penalty;
@@ -286,7 +592,7 @@ fn penalize<'a, 'b>(
 
         // 6: Roll out.
         if is_roll_out(curr.finger, old1.finger) {
-            let penalty = 0.125 * count;
+            let penalty = tables.roll_out * count;
             if detailed {
                 *result[6].high_keys.entry(slice2).or_insert(0.0) += penalty;
                 result[6].total += penalty;
@@ -296,7 +602,7 @@ fn penalize<'a, 'b>(
 
         // 7: Roll in.
         if is_roll_in(curr.finger, old1.finger) {
-            let penalty = -0.125 * count;
+            let penalty = -tables.roll_in * count;
             if detailed {
                 *result[7].high_keys.entry(slice2).or_insert(0.0) += penalty;
                 result[7].total += penalty;
@@ -306,7 +612,7 @@ fn penalize<'a, 'b>(
 
         // 11. Same key.
         if curr.pos == old1.pos {
-            let penalty = calculate_same_key_penalty(curr, old1);
+            let penalty = calculate_same_key_penalty(curr, old1, tables);
             let penalty = penalty * count;
             if detailed && penalty > 0. {
                 *result[11].high_keys.entry(slice2).or_insert(0.0) += penalty;
@@ -323,7 +629,7 @@ fn penalize<'a, 'b>(
     };
 
     if curr.hand == old1.hand && old1.hand == old2.hand {
-        let slice3 = &string[(len - 3)..len];
+        let slice3 = &string[tail_start(3)..end];
 
         // 5: Roll reversal.
         if (curr.finger == Finger::Middle &&
@@ -332,7 +638,7 @@ fn penalize<'a, 'b>(
            (curr.finger == Finger::Ring &&
             old1.finger == Finger::Pinky &&
             old2.finger == Finger::Middle) {
-            let penalty = 20.0 * count;
+            let penalty = tables.roll_reversal * count;
             if detailed {
                 *result[5].high_keys.entry(slice3).or_insert(0.0) += penalty;
                 result[5].total += penalty;
@@ -345,7 +651,7 @@ fn penalize<'a, 'b>(
             (curr.row == Row::Bottom && old1.row == Row::Home && old2.row == Row::Top)) &&
            ((is_roll_out(curr.finger, old1.finger) && is_roll_out(old1.finger, old2.finger)) ||
                (is_roll_in(curr.finger, old1.finger) && is_roll_in(old1.finger, old2.finger))) {
-            let penalty = 10.0 * count;
+            let penalty = tables.twist * count;
             if detailed {
                 *result[9].high_keys.entry(slice3).or_insert(0.0) += penalty;
                 result[9].total += penalty;
@@ -360,7 +666,7 @@ fn penalize<'a, 'b>(
            (curr.finger == Finger::Pinky &&
             old1.finger == Finger::Ring &&
             old2.finger == Finger::Pinky) {
-            let penalty = 15.0 * count;
+            let penalty = tables.pinky_ring_alternation * count;
             if detailed {
                 *result[10].high_keys.entry(slice3).or_insert(0.0) += penalty;
                 result[10].total += penalty;
@@ -373,8 +679,8 @@ fn penalize<'a, 'b>(
     if curr.hand == old2.hand && curr.finger == old2.finger {
         if curr.row == Row::Top && old2.row == Row::Bottom ||
            curr.row == Row::Bottom && old2.row == Row::Top {
-            let slice3 = &string[(len - 3)..len];
-            let penalty = 3.0 * count;
+            let slice3 = &string[tail_start(3)..end];
+            let penalty = tables.long_jump_sandwich * count;
             if detailed {
                 *result[8].high_keys.entry(slice3).or_insert(0.0) += penalty;
                 result[8].total += penalty;
@@ -391,8 +697,8 @@ fn penalize<'a, 'b>(
 
     if curr.hand == old1.hand && old1.hand == old2.hand && old2.hand == old3.hand {
         // 4: Same hand.
-        let slice4 = &string[(len - 4)..len];
-        let penalty = 0.1 * count;
+        let slice4 = &string[tail_start(4)..end];
+        let penalty = tables.same_hand * count;
         if detailed {
             *result[4].high_keys.entry(slice4).or_insert(0.0) += penalty;
             result[4].total += penalty;
@@ -400,8 +706,8 @@ fn penalize<'a, 'b>(
         total += penalty;
     } else if curr.hand != old1.hand && old1.hand != old2.hand && old2.hand != old3.hand {
         // 1: Alternating hand.
-        let slice4 = &string[(len - 4)..len];
-        let penalty = 0.5 * count;
+        let slice4 = &string[tail_start(4)..end];
+        let penalty = tables.alternating_hand * count;
         if detailed {
             *result[1].high_keys.entry(slice4).or_insert(0.0) += penalty;
             result[1].total += penalty;
@@ -412,7 +718,8 @@ fn penalize<'a, 'b>(
     total
 }
 
-fn calculate_same_finger_penalty(curr: &KeyPress, old1: &KeyPress)
+fn calculate_same_finger_penalty(
+    curr: &KeyPress, old1: &KeyPress, geometry: &KeyboardGeometry, tables: &TaperedPenaltyTables)
 -> f64 {
 
     // This penalty should only be calculated if we consecutively use the
@@ -421,599 +728,129 @@ fn calculate_same_finger_penalty(curr: &KeyPress, old1: &KeyPress)
     assert!(curr.finger == old1.finger);
     assert!(curr.pos != old1.pos);
 
-    if curr.finger == Finger::Index {
-        // In the following comments, all letter combinations are on Qwerty.
-
-        // fg/gf/hj/jh
-        if curr.pos == 14 && old1.pos == 15 ||
-           curr.pos == 15 && old1.pos == 14 ||
-           curr.pos == 16 && old1.pos == 17 ||
-           curr.pos == 17 && old1.pos == 16 {
-            return 0.;
-        }
-        // gr/rg/hu/uh
-        if curr.pos == 15 && old1.pos == 3 ||
-           curr.pos == 3 && old1.pos == 15 ||
-           curr.pos == 16 && old1.pos == 6 ||
-           curr.pos == 6 && old1.pos == 16 {
-            return 0.;
-        }
-        // bf/fb/nj/jn
-        if curr.pos == 26 && old1.pos == 14 ||
-           curr.pos == 14 && old1.pos == 26 ||
-           curr.pos == 27 && old1.pos == 17 ||
-           curr.pos == 17 && old1.pos == 27 {
-            return 1.;
-        }
-        // rt/tr/yu/uy
-        if curr.pos == 3 && old1.pos == 4 ||
-           curr.pos == 4 && old1.pos == 3 ||
-           curr.pos == 5 && old1.pos == 6 ||
-           curr.pos == 6 && old1.pos == 5 {
-            return 3.;
-        }
-        // vf/fv/mj/jm
-        if curr.pos == 25 && old1.pos == 14 ||
-           curr.pos == 14 && old1.pos == 25 ||
-           curr.pos == 28 && old1.pos == 17 ||
-           curr.pos == 17 && old1.pos == 28 {
-            return 3.;
-        }
-        // fr/rf/ju/uj
-        if curr.pos == 14 && old1.pos == 3 ||
-           curr.pos == 3 && old1.pos == 14 ||
-           curr.pos == 17 && old1.pos == 6 ||
-           curr.pos == 6 && old1.pos == 17 {
-            return 4.;
-        }
-        // br/rb/nu/un
-        if curr.pos == 26 && old1.pos == 3 ||
-           curr.pos == 3 && old1.pos == 26 ||
-           curr.pos == 27 && old1.pos == 6 ||
-           curr.pos == 6 && old1.pos == 27 {
-            return 6.;
-        }
-        // bv/vb/nm/mn
-        if curr.pos == 26 && old1.pos == 25 ||
-           curr.pos == 25 && old1.pos == 26 ||
-           curr.pos == 27 && old1.pos == 28 ||
-           curr.pos == 28 && old1.pos == 27 {
-            return 7.;
-        }
-        // vr/rv/mu/um
-        if curr.pos == 25 && old1.pos == 3 ||
-           curr.pos == 3 && old1.pos == 25 ||
-           curr.pos == 28 && old1.pos == 6 ||
-           curr.pos == 6 && old1.pos == 28 {
-            return 8.;
-        }
-        // ft/tf/jy/yj
-        if curr.pos == 14 && old1.pos == 4 ||
-           curr.pos == 4 && old1.pos == 14 ||
-           curr.pos == 17 && old1.pos == 5 ||
-           curr.pos == 5 && old1.pos == 17 {
-            return 10.;
-        }
-        // vg/gv/mh/hm
-        if curr.pos == 25 && old1.pos == 15 ||
-           curr.pos == 15 && old1.pos == 25 ||
-           curr.pos == 28 && old1.pos == 16 ||
-           curr.pos == 16 && old1.pos == 28 {
-            return 10.;
-        }
-        // bg/gb/nh/hn
-        if curr.pos == 26 && old1.pos == 15 ||
-           curr.pos == 15 && old1.pos == 26 ||
-           curr.pos == 27 && old1.pos == 16 ||
-           curr.pos == 16 && old1.pos == 27 {
-            return 15.;
-        }
-        // gt/tg/hy/yh
-        if curr.pos == 15 && old1.pos == 4 ||
-           curr.pos == 4 && old1.pos == 15 ||
-           curr.pos == 16 && old1.pos == 5 ||
-           curr.pos == 5 && old1.pos == 16 {
-            return 15.;
-        }
-        // bt/tb/ny/yn
-        if curr.pos == 26 && old1.pos == 4 ||
-           curr.pos == 4 && old1.pos == 26 ||
-           curr.pos == 27 && old1.pos == 5 ||
-           curr.pos == 5 && old1.pos == 27 {
-            return 20.;
-        }
-        // vt/tv/my/ym
-        if curr.pos == 25 && old1.pos == 4 ||
-           curr.pos == 4 && old1.pos == 25 ||
-           curr.pos == 28 && old1.pos == 5 ||
-           curr.pos == 5 && old1.pos == 28 {
-            return 25.;
-        }
-
-        assert!(false, "All index finger pairs must be covered by now");
-    }
-
-    assert!(!curr.center,
-            "All center column key presses must be covered by now.");
-
-    let long_jump = (curr.row == Row::Top && old1.row == Row::Bottom) ||
-                    (curr.row == Row::Bottom && old1.row == Row::Top);
+    // Blend the comfort and speed profiles' precomputed tables, falling
+    // back to the geometry formula directly for thumb-cluster keys,
+    // which the tables don't cover.
+    tables.same_finger(old1.pos, curr.pos, geometry)
+}
 
-    // Long jumping is painful: 15 points; else 5 points.
-    0.0 + if long_jump { 15.0 } else { 5.0 }
-        + if curr.outer { 5.0 } else { 0.0 }
-        + if old1.outer { 5.0 } else { 0.0 }
+fn same_finger_distance_penalty(old1: usize, curr: usize, geometry: &KeyboardGeometry, coeff: f64, long_jump_surcharge: f64)
+-> f64 {
+    // Derive the penalty from the physical distance between the two keys
+    // rather than an enumerated table of position pairs: the farther a
+    // finger has to travel to hit the next key, the more it costs. This
+    // naturally covers the "long jump" (top row to bottom row) case too,
+    // which just gets an extra surcharge on top of the distance.
+    let distance = geometry.distance(old1, curr);
+
+    let curr_row = geometry.key(curr).row;
+    let old1_row = geometry.key(old1).row;
+    let long_jump = (curr_row == Row::Top && old1_row == Row::Bottom) ||
+                    (curr_row == Row::Bottom && old1_row == Row::Top);
+
+    distance * coeff
+        + if long_jump { long_jump_surcharge } else { 0.0 }
 }
 
-fn calculate_stretch_penalty(curr: &KeyPress, old1: &KeyPress)
+fn calculate_stretch_penalty(
+    curr: &KeyPress, old1: &KeyPress, geometry: &KeyboardGeometry, tables: &TaperedPenaltyTables)
 -> f64 {
     // This penalty should only be calculated if we use different fingers on
     // the same hand.
     assert!(curr.hand == old1.hand);
     assert!(curr.finger != old1.finger);
 
-    // In the following comments, all letter combinations are on Qwerty.
-
-    // 1 point penalties.
-
-    // ve ev mi im
-    if curr.pos == 25 && old1.pos == 2 ||
-       curr.pos == 2 && old1.pos == 25 ||
-       curr.pos == 28 && old1.pos == 7 ||
-       curr.pos == 7 && old1.pos == 28 {
-        return 1.;
-    }
-
-    // vw wv mo om
-    if curr.pos == 25 && old1.pos == 1 ||
-       curr.pos == 1 && old1.pos == 25 ||
-       curr.pos == 28 && old1.pos == 8 ||
-       curr.pos == 8 && old1.pos == 28 {
-        return 1.;
-    }
-
-    // ba ab n; ;n
-    if curr.pos == 26 && old1.pos == 11 ||
-       curr.pos == 11 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 20 ||
-       curr.pos == 20 && old1.pos == 27 {
-        return 1.;
-    }
-
-    // gq qg hp ph
-    if curr.pos == 15 && old1.pos == 0 ||
-       curr.pos == 0 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 9 ||
-       curr.pos == 9 && old1.pos == 16 {
-        return 1.;
-    }
-
-    // bz zb n/ /n
-    if curr.pos == 26 && old1.pos == 22 ||
-       curr.pos == 22 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 31 ||
-       curr.pos == 31 && old1.pos == 27 {
-        return 1.;
-    }
-
-    // ga ag h; ;h
-    if curr.pos == 15 && old1.pos == 11 ||
-       curr.pos == 11 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 20 ||
-       curr.pos == 20 && old1.pos == 16 {
-        return 1.;
-    }
-
-    // tq qt yp py
-    if curr.pos == 4 && old1.pos == 0 ||
-       curr.pos == 0 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 9 ||
-       curr.pos == 9 && old1.pos == 5 {
-        return 1.;
-    }
-
-    // i' 'i
-    if curr.pos == 7 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 7 {
-        return 1.;
-    }
-
-    // u' 'u
-    if curr.pos == 6 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 6 {
-        return 1.;
-    }
+    // Like the same-finger penalty, blend the comfort and speed tables
+    // and only fall back to the geometry formula for thumb-cluster keys.
+    let mut penalty = tables.stretch(old1.pos, curr.pos, geometry);
 
-    // ta at y; ;y
-    if curr.pos == 4 && old1.pos == 11 ||
-       curr.pos == 11 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 20 ||
-       curr.pos == 20 && old1.pos == 5 {
-        return 1.;
+    // A stretch that reaches into the center column is an awkward
+    // off-hand-adjacent reach on top of whatever its raw distance is.
+    if curr.center || old1.center {
+        penalty += tables.center_stretch_surcharge;
     }
 
-    // gz zg h/ /h
-    if curr.pos == 15 && old1.pos == 22 ||
-       curr.pos == 22 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 31 ||
-       curr.pos == 31 && old1.pos == 16 {
-        return 1.;
-    }
-
-    // bs sb nl ln
-    if curr.pos == 26 && old1.pos == 12 ||
-       curr.pos == 12 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 19 ||
-       curr.pos == 19 && old1.pos == 27 {
-        return 1.;
-    }
-
-    // gw wg ho oh
-    if curr.pos == 15 && old1.pos == 1 ||
-       curr.pos == 1 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 8 ||
-       curr.pos == 8 && old1.pos == 16 {
-        return 1.;
-    }
-
-    // bx xb n. .n
-    if curr.pos == 26 && old1.pos == 23 ||
-       curr.pos == 23 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 30 ||
-       curr.pos == 30 && old1.pos == 27 {
-        return 1.;
-    }
-
-    // gs sg hl lh
-    if curr.pos == 15 && old1.pos == 12 ||
-       curr.pos == 12 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 19 ||
-       curr.pos == 19 && old1.pos == 16 {
-        return 1.;
-    }
-
-    // tw wt yo oy
-    if curr.pos == 4 && old1.pos == 1 ||
-       curr.pos == 1 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 8 ||
-       curr.pos == 8 && old1.pos == 5 {
-        return 1.;
-    }
-
-    // j\ \j
-    if curr.pos == 17 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 17 {
-        return 1.;
-    }
-
-    // m' 'm
-    if curr.pos == 28 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 28 {
-        return 1.;
-    }
-
-    // o' 'o
-    if curr.pos == 8 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 8 {
-        return 1.;
-    }
-
-    // 2 point penalties.
-
-    // ez ze i/ /i
-    if curr.pos == 2 && old1.pos == 22 ||
-       curr.pos == 22 && old1.pos == 2 ||
-       curr.pos == 7 && old1.pos == 31 ||
-       curr.pos == 31 && old1.pos == 7 {
-        return 2.;
-    }
-
-    // rz zr u/ /u
-    if curr.pos == 3 && old1.pos == 22 ||
-       curr.pos == 22 && old1.pos == 3 ||
-       curr.pos == 6 && old1.pos == 31 ||
-       curr.pos == 31 && old1.pos == 6 {
-        return 2.;
-    }
-
-    // bd db nk kn
-    if curr.pos == 26 && old1.pos == 13 ||
-       curr.pos == 13 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 18 ||
-       curr.pos == 18 && old1.pos == 27 {
-        return 2.;
-    }
-
-    // ge eg hi ih
-    if curr.pos == 15 && old1.pos == 2 ||
-       curr.pos == 2 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 7 ||
-       curr.pos == 7 && old1.pos == 16 {
-        return 2.;
-    }
-
-    // bc cb n, ,n
-    if curr.pos == 26 && old1.pos == 24 ||
-       curr.pos == 24 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 29 ||
-       curr.pos == 29 && old1.pos == 27 {
-        return 2.;
-    }
-
-    // gd dg hk kh
-    if curr.pos == 15 && old1.pos == 13 ||
-       curr.pos == 13 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 18 ||
-       curr.pos == 18 && old1.pos == 16 {
-        return 2.;
-    }
-
-    // te et yi iy
-    if curr.pos == 4 && old1.pos == 2 ||
-       curr.pos == 2 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 7 ||
-       curr.pos == 7 && old1.pos == 5 {
-        return 2.;
-    }
-
-    // xa ax .; ;.
-    if curr.pos == 23 && old1.pos == 11 ||
-       curr.pos == 11 && old1.pos == 23 ||
-       curr.pos == 30 && old1.pos == 20 ||
-       curr.pos == 20 && old1.pos == 30 {
-        return 2.;
-    }
-
-    // sq qs lp pl
-    if curr.pos == 12 && old1.pos == 0 ||
-       curr.pos == 0 && old1.pos == 12 ||
-       curr.pos == 19 && old1.pos == 9 ||
-       curr.pos == 9 && old1.pos == 19 {
-        return 2.;
-    }
-
-    // vq qv mp pm
-    if curr.pos == 25 && old1.pos == 0 ||
-       curr.pos == 0 && old1.pos == 25 ||
-       curr.pos == 28 && old1.pos == 9 ||
-       curr.pos == 9 && old1.pos == 28 {
-        return 2.;
-    }
-
-    // 3 point penalties.
-
-    // bw wb no on
-    if curr.pos == 26 && old1.pos == 1 ||
-       curr.pos == 1 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 8 ||
-       curr.pos == 8 && old1.pos == 27 {
-        return 3.;
-    }
-
-    // gx xg h. .h
-    if curr.pos == 15 && old1.pos == 23 ||
-       curr.pos == 23 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 30 ||
-       curr.pos == 30 && old1.pos == 16 {
-        return 3.;
-    }
-
-    // ts st yl ly
-    if curr.pos == 4 && old1.pos == 12 ||
-       curr.pos == 12 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 19 ||
-       curr.pos == 19 && old1.pos == 5 {
-        return 3.;
-    }
-
-    // rx xr u. .u
-    if curr.pos == 3 && old1.pos == 23 ||
-       curr.pos == 23 && old1.pos == 3 ||
-       curr.pos == 6 && old1.pos == 30 ||
-       curr.pos == 30 && old1.pos == 6 {
-        return 3.;
-    }
-
-    // m\ \m
-    if curr.pos == 28 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 28 {
-        return 3.;
-    }
-
-    // bq qb np pn
-    if curr.pos == 26 && old1.pos == 0 ||
-       curr.pos == 0 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 9 ||
-       curr.pos == 9 && old1.pos == 27 {
-        return 3.;
-    }
-
-    // k\ \k
-    if curr.pos == 18 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 18 {
-        return 3.;
-    }
-
-    // ,' ',
-    if curr.pos == 29 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 29 {
-        return 3.;
-    }
-
-    // .' '.
-    if curr.pos == 30 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 30 {
-        return 3.;
-    }
-
-    // l\ \l
-    if curr.pos == 19 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 19 {
-        return 3.;
-    }
-
-    // y\ \y
-    if curr.pos == 5 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 5 {
-        return 3.;
-    }
-
-    // h' 'h
-    if curr.pos == 16 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 16 {
-        return 3.;
-    }
-
-    // 4 point penalties.
-
-    // tz zt y/ /y
-    if curr.pos == 4 && old1.pos == 22 ||
-       curr.pos == 22 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 31 ||
-       curr.pos == 31 && old1.pos == 5 {
-        return 4.;
-    }
-
-    // td dt yk ky
-    if curr.pos == 4 && old1.pos == 13 ||
-       curr.pos == 13 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 18 ||
-       curr.pos == 18 && old1.pos == 5 {
-        return 4.;
-    }
-
-    // gc cg h, ,h
-    if curr.pos == 15 && old1.pos == 24 ||
-       curr.pos == 24 && old1.pos == 15 ||
-       curr.pos == 16 && old1.pos == 29 ||
-       curr.pos == 29 && old1.pos == 16 {
-        return 4.;
-    }
-
-    // ex xe i. .i
-    if curr.pos == 2 && old1.pos == 23 ||
-       curr.pos == 23 && old1.pos == 2 ||
-       curr.pos == 7 && old1.pos == 30 ||
-       curr.pos == 30 && old1.pos == 7 {
-        return 4.;
-    }
-
-    // rc cr u, ,u
-    if curr.pos == 3 && old1.pos == 24 ||
-       curr.pos == 24 && old1.pos == 3 ||
-       curr.pos == 6 && old1.pos == 29 ||
-       curr.pos == 29 && old1.pos == 6 {
-        return 4.;
-    }
-
-    // cw wc ,o o,
-    if curr.pos == 24 && old1.pos == 1 ||
-       curr.pos == 1 && old1.pos == 24 ||
-       curr.pos == 29 && old1.pos == 8 ||
-       curr.pos == 8 && old1.pos == 29 {
-        return 4.;
-    }
-
-    // n' 'n
-    if curr.pos == 27 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 27 {
-        return 4.;
-    }
-
-    // h\ \h
-    if curr.pos == 16 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 16 {
-        return 4.;
-    }
-
-    // 5 point penalties.
-
-    // y' 'y
-    if curr.pos == 5 && old1.pos == 21 ||
-       curr.pos == 21 && old1.pos == 5 {
-        return 5.;
-    }
-
-    // tx xt y. .y
-    if curr.pos == 4 && old1.pos == 23 ||
-       curr.pos == 23 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 30 ||
-       curr.pos == 30 && old1.pos == 5 {
-        return 5.
-    }
-
-    // 6 point penalties.
+    penalty
+}
 
-    // tc ct y, ,y
-    if curr.pos == 4 && old1.pos == 24 ||
-       curr.pos == 24 && old1.pos == 4 ||
-       curr.pos == 5 && old1.pos == 29 ||
-       curr.pos == 29 && old1.pos == 5 {
-        return 6.;
-    }
+/// The cost of reaching `curr` through the `Activator` at `activator_pos`:
+/// the keystroke cost of the hold/tap itself (discounted to zero for a
+/// `Momentary` activator still held from the immediately preceding
+/// keystroke), plus a same-finger/stretch-style conflict penalty if the
+/// activator shares a hand with `curr`.
+fn calculate_layer_switch_penalty(
+    curr: &KeyPress, activator_pos: usize, old1: &Option<KeyPress>,
+    geometry: &KeyboardGeometry, tables: &TaperedPenaltyTables,
+    activator_modes: &HashMap<usize, ActivationMode>)
+-> f64
+{
+    let mode = activator_modes.get(&activator_pos).cloned();
 
-    // 7 point penalties.
+    let still_held = mode == Some(ActivationMode::Momentary) &&
+        old1.as_ref().map_or(false, |o| o.layer == curr.layer && o.activator_pos == Some(activator_pos));
 
-    // be eb ni in
-    if curr.pos == 26 && old1.pos == 2 ||
-       curr.pos == 2 && old1.pos == 26 ||
-       curr.pos == 27 && old1.pos == 7 ||
-       curr.pos == 7 && old1.pos == 27 {
-        return 7.;
-    }
+    let mut penalty = if still_held { 0.0 } else { tables.activator_keystroke };
 
-    // cq qc ,p p,
-    if curr.pos == 24 && old1.pos == 0 ||
-       curr.pos == 0 && old1.pos == 24 ||
-       curr.pos == 29 && old1.pos == 9 ||
-       curr.pos == 9 && old1.pos == 29 {
-        return 7.;
+    let activator_key = geometry.key(activator_pos);
+    if activator_key.hand == curr.hand {
+        penalty += if activator_key.finger == curr.finger {
+            tables.same_finger(activator_pos, curr.pos, geometry)
+        } else {
+            tables.stretch(activator_pos, curr.pos, geometry)
+        };
     }
 
-    // 8 point penalties.
+    penalty
+}
 
-    // n\ \n
-    if curr.pos == 27 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 27 {
-        return 8.;
+/// The Shift key `shift` would use to type `curr`: whichever of
+/// `left_pos`/`right_pos` is opposite-hand from `curr`, falling back to
+/// `left_pos` if both happen to share `curr`'s hand.
+fn shift_pos_for(curr: &KeyPress, shift: &ShiftKeys, geometry: &KeyboardGeometry)
+-> usize
+{
+    if geometry.key(shift.left_pos).hand != curr.hand {
+        shift.left_pos
+    } else if geometry.key(shift.right_pos).hand != curr.hand {
+        shift.right_pos
+    } else {
+        shift.left_pos
     }
+}
 
-    // 9 point penalties.
-
-    // xq qx .p p.
-    if curr.pos == 23 && old1.pos == 0 ||
-       curr.pos == 0 && old1.pos == 23 ||
-       curr.pos == 30 && old1.pos == 9 ||
-       curr.pos == 9 && old1.pos == 30 {
-        return 9.;
+/// The cost of typing `curr` uppercase: zero if `curr` isn't uppercase or
+/// the layout has no `ShiftKeys` at all (the call site already guards the
+/// latter); otherwise the keystroke cost of engaging Shift (discounted to
+/// zero for a `Momentary` Shift still held from an uppercase `old1`),
+/// plus a same-finger/stretch-style conflict penalty if the Shift key
+/// picked shares a hand with `curr`.
+fn calculate_shift_penalty(curr: &KeyPress, old1: &Option<KeyPress>, shift: &ShiftKeys, geometry: &KeyboardGeometry, tables: &TaperedPenaltyTables)
+-> f64
+{
+    if !curr.kc.is_uppercase() {
+        return 0.0;
     }
 
-    // .\ \.
-    if curr.pos == 30 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 30 {
-        return 9.;
-    }
+    let shift_pos = shift_pos_for(curr, shift, geometry);
 
-    // ,\ \,
-    if curr.pos == 29 && old1.pos == 10 ||
-       curr.pos == 10 && old1.pos == 29 {
-        return 9.;
-    }
+    let still_held = shift.mode == ActivationMode::Momentary &&
+        old1.as_ref().map_or(false, |o| o.kc.is_uppercase() && shift_pos_for(o, shift, geometry) == shift_pos);
 
-    // 10 point penalties.
+    let mut penalty = if still_held { 0.0 } else { tables.shift_keystroke };
 
-    // wz zw o/ /o
-    if curr.pos == 1 && old1.pos == 22 ||
-       curr.pos == 22 && old1.pos == 1 ||
-       curr.pos == 8 && old1.pos == 31 ||
-       curr.pos == 31 && old1.pos == 8 {
-        return 10.;
+    let shift_key = geometry.key(shift_pos);
+    if shift_key.hand == curr.hand {
+        penalty += if shift_key.finger == curr.finger {
+            tables.same_finger(shift_pos, curr.pos, geometry)
+        } else {
+            tables.stretch(shift_pos, curr.pos, geometry)
+        };
     }
 
-    0.
+    penalty
 }
 
-fn calculate_same_key_penalty(curr: &KeyPress, old1: &KeyPress)
+fn calculate_same_key_penalty(curr: &KeyPress, old1: &KeyPress, tables: &TaperedPenaltyTables)
 -> f64 {
 
     // This penalty should only be calculated if we consecutively use the same
@@ -1023,12 +860,12 @@ fn calculate_same_key_penalty(curr: &KeyPress, old1: &KeyPress)
     assert!(curr.pos == old1.pos);
 
     match curr.finger {
-        Finger::Pinky  => 3.0,
+        Finger::Pinky  => tables.same_key_pinky(),
         _ => 0.
     }
 }
 
-fn is_roll_out(curr: Finger, prev: Finger) -> bool {
+pub(crate) fn is_roll_out(curr: Finger, prev: Finger) -> bool {
     match curr {
         Finger::Thumb  => false,
         Finger::Index  => prev == Finger::Thumb,
@@ -1038,7 +875,7 @@ fn is_roll_out(curr: Finger, prev: Finger) -> bool {
     }
 }
 
-fn is_roll_in(curr: Finger, prev: Finger) -> bool {
+pub(crate) fn is_roll_in(curr: Finger, prev: Finger) -> bool {
     match curr {
         Finger::Thumb  => prev != Finger::Thumb,
         Finger::Index  => prev != Finger::Thumb && prev != Finger::Index,
@@ -1047,3 +884,46 @@ fn is_roll_in(curr: Finger, prev: Finger) -> bool {
         Finger::Pinky  => false,
     }
 }
+
+/// The one-key base penalty for `pos`, i.e. `BASE_PENALTY` without the
+/// per-occurrence count folded in. Exposed so `incremental.rs` can price
+/// a lone position the same way `penalize`'s one-key section does.
+pub(crate) fn base_penalty(pos: usize)
+-> f64
+{
+    BASE_PENALTY.0[pos]
+}
+
+/// Sum of the two-key bigram penalties (same finger, stretch, roll
+/// in/out, same key) for `curr` typed right after `old1`, mirroring the
+/// two-key section of `penalize` but without the per-category breakdown
+/// `detailed` mode needs. Used by `incremental.rs`, which only ever
+/// needs the combined number for a single affected bigram.
+pub(crate) fn two_key_penalty(
+    curr: &KeyPress, old1: &KeyPress, geometry: &KeyboardGeometry, tables: &TaperedPenaltyTables)
+-> f64
+{
+    if curr.hand != old1.hand {
+        return 0.0;
+    }
+
+    let mut total = 0.0;
+
+    if curr.finger == old1.finger && curr.pos != old1.pos {
+        total += calculate_same_finger_penalty(curr, old1, geometry, tables);
+    }
+    if curr.finger != old1.finger {
+        total += calculate_stretch_penalty(curr, old1, geometry, tables);
+    }
+    if is_roll_out(curr.finger, old1.finger) {
+        total += tables.roll_out;
+    }
+    if is_roll_in(curr.finger, old1.finger) {
+        total -= tables.roll_in;
+    }
+    if curr.pos == old1.pos {
+        total += calculate_same_key_penalty(curr, old1, tables);
+    }
+
+    total
+}
@@ -0,0 +1,117 @@
+/// A data-driven replacement for the bigram-penalty if-chains that used
+/// to live in `penalty.rs`: a flat, indexable lookup table instead of a
+/// long chain of `curr.pos == X && old1.pos == Y` comparisons. This is
+/// the same piece-square-table idea chess engines use to replace branchy
+/// evaluation with array lookups.
+///
+/// The table only covers the 36 "letter" positions (the three finger
+/// rows); thumb-cluster keys fall outside it and the caller is expected
+/// to fall back to a geometry-derived penalty for those.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+pub const PENALTY_TABLE_SIZE: usize = 36;
+
+pub struct PenaltyTable([[f64; PENALTY_TABLE_SIZE]; PENALTY_TABLE_SIZE]);
+
+pub struct PenaltyTableBuilder
+{
+    table: [[f64; PENALTY_TABLE_SIZE]; PENALTY_TABLE_SIZE],
+}
+
+impl PenaltyTableBuilder
+{
+    pub fn new()
+    -> PenaltyTableBuilder
+    {
+        PenaltyTableBuilder {
+            table: [[0.0; PENALTY_TABLE_SIZE]; PENALTY_TABLE_SIZE],
+        }
+    }
+
+    /// Sets the penalty for the ordered pair `(old1, curr)`.
+    pub fn set(&mut self, old1: usize, curr: usize, value: f64)
+    -> &mut PenaltyTableBuilder
+    {
+        assert!(old1 < PENALTY_TABLE_SIZE, "old1 index {} out of range", old1);
+        assert!(curr < PENALTY_TABLE_SIZE, "curr index {} out of range", curr);
+        self.table[old1][curr] = value;
+        self
+    }
+
+    /// Sets the penalty for both `(a, b)` and `(b, a)`, matching the
+    /// `X&&Y || Y&&X` structure the old if-chains used to need to cover
+    /// both typing orders of a bigram.
+    pub fn set_symmetric(&mut self, a: usize, b: usize, value: f64)
+    -> &mut PenaltyTableBuilder
+    {
+        self.set(a, b, value);
+        self.set(b, a, value);
+        self
+    }
+
+    pub fn build(self)
+    -> PenaltyTable
+    {
+        PenaltyTable(self.table)
+    }
+}
+
+impl PenaltyTable
+{
+    /// Returns the penalty for typing `curr` right after `old1`, or
+    /// `None` if either position falls outside the table (e.g. a
+    /// thumb-cluster key), in which case the caller should fall back to
+    /// a geometry-derived penalty.
+    pub fn get(&self, old1: usize, curr: usize)
+    -> Option<f64>
+    {
+        if old1 < PENALTY_TABLE_SIZE && curr < PENALTY_TABLE_SIZE {
+            Some(self.0[old1][curr])
+        } else {
+            None
+        }
+    }
+
+    /// Loads table overrides from a simple text format: one `old1 curr
+    /// value` triple per line, optionally followed by `sym` to also set
+    /// the reverse pair. Lines starting with `#` and blank lines are
+    /// ignored. Positions not mentioned in the file keep whatever value
+    /// `base` already had, so a file only needs to describe overrides.
+    pub fn load(path: &str, base: PenaltyTable)
+    -> Result<PenaltyTable, String>
+    {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut builder = PenaltyTableBuilder { table: base.0 };
+
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("{}: {}", path, e))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 && fields.len() != 4 {
+                return Err(format!("{}: expected 'old1 curr value [sym]', got '{}'", path, line));
+            }
+
+            let old1: usize = fields[0].parse().map_err(|_| format!("{}: bad old1 in '{}'", path, line))?;
+            let curr: usize = fields[1].parse().map_err(|_| format!("{}: bad curr in '{}'", path, line))?;
+            let value: f64 = fields[2].parse().map_err(|_| format!("{}: bad value in '{}'", path, line))?;
+
+            if old1 >= PENALTY_TABLE_SIZE || curr >= PENALTY_TABLE_SIZE {
+                return Err(format!("{}: index out of range 0..{} in '{}'", path, PENALTY_TABLE_SIZE, line));
+            }
+
+            if fields.len() == 4 && fields[3] == "sym" {
+                builder.set_symmetric(old1, curr, value);
+            } else {
+                builder.set(old1, curr, value);
+            }
+        }
+
+        Ok(builder.build())
+    }
+}
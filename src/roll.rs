@@ -0,0 +1,150 @@
+/// Extends the adjacent-pair roll classification in `penalty.rs`
+/// (`is_roll_in`/`is_roll_out`) to full roll runs.
+///
+/// The quartad scoring in `penalty.rs` only ever looks at a 4-key
+/// window, so it can reward or penalize a roll-in/roll-out pair but
+/// can't see a sustained 3+ key roll across one hand, or a run that
+/// reverses direction partway through. `RollAnalyzer` walks the whole
+/// keystroke sequence instead of a fixed window to find those runs,
+/// built on top of the same `is_roll_in`/`is_roll_out` primitives.
+
+use layout::KeyPress;
+use layout::LayoutPosMap;
+use penalty::is_roll_in;
+use penalty::is_roll_out;
+
+/// How the bonus for a sustained roll run scales with its length, in
+/// keys. Runs of 2 keys (a single roll-in/roll-out pair) are already
+/// priced by `is_roll_in`/`is_roll_out` in `penalize`; this only kicks
+/// in for runs of 3 or more.
+pub struct RollBonusCurve
+{
+    pub per_extra_key: f64,
+    pub max_bonus:     f64,
+}
+
+impl RollBonusCurve
+{
+    pub fn new(per_extra_key: f64, max_bonus: f64)
+    -> RollBonusCurve
+    {
+        RollBonusCurve { per_extra_key: per_extra_key, max_bonus: max_bonus }
+    }
+
+    fn bonus_for_length(&self, run_len_keys: usize)
+    -> f64
+    {
+        if run_len_keys < 3 {
+            return 0.0;
+        }
+        let bonus = (run_len_keys - 2) as f64 * self.per_extra_key;
+        bonus.min(self.max_bonus)
+    }
+}
+
+static DEFAULT_PER_EXTRA_KEY: f64 = 0.25;
+static DEFAULT_MAX_BONUS:     f64 = 2.0;
+static DEFAULT_REVERSAL_PENALTY: f64 = 5.0;
+
+pub struct RollAnalyzer
+{
+    pub curve:            RollBonusCurve,
+    pub reversal_penalty: f64,
+}
+
+impl RollAnalyzer
+{
+    pub fn new(curve: RollBonusCurve, reversal_penalty: f64)
+    -> RollAnalyzer
+    {
+        RollAnalyzer { curve: curve, reversal_penalty: reversal_penalty }
+    }
+
+    pub fn default_analyzer()
+    -> RollAnalyzer
+    {
+        RollAnalyzer::new(RollBonusCurve::new(DEFAULT_PER_EXTRA_KEY, DEFAULT_MAX_BONUS), DEFAULT_REVERSAL_PENALTY)
+    }
+
+    /// Walks `string` through `position_map`, finding maximal monotonic
+    /// same-hand roll-in/roll-out runs, and returns the total adjustment
+    /// across the whole string: a discount (negative) that grows with
+    /// run length for sustained rolls, plus a penalty when a run is
+    /// immediately followed by an opposite-direction run on the same
+    /// hand (e.g. a roll-in run reversing straight into a roll-out run).
+    pub fn analyze(&self, string: &str, position_map: &LayoutPosMap)
+    -> f64
+    {
+        // Keep a `None` slot for every character `position_map` doesn't
+        // define, the same way `penalty::prepare_quartad_list` keeps its
+        // window aligned to what was actually typed -- collapsing them
+        // out with `filter_map` would splice the keys before and after
+        // an unmapped character together as though they were pressed
+        // back-to-back.
+        let keys: Vec<Option<KeyPress>> = string.chars()
+            .map(|c| position_map.get_key(c))
+            .collect();
+
+        if keys.len() < 2 {
+            return 0.0;
+        }
+
+        let mut total = 0.0;
+        let mut prev_run: Option<(bool, usize)> = None; // (direction, last pair index of the run)
+        let mut i = 1;
+
+        while i < keys.len() {
+            let dir = match classify(&keys[i - 1], &keys[i]) {
+                Some(d) => d,
+                None => { i += 1; continue; }
+            };
+
+            let run_start_pair = i;
+            let mut run_end_pair = i;
+            while run_end_pair + 1 < keys.len()
+                && classify(&keys[run_end_pair], &keys[run_end_pair + 1]) == Some(dir) {
+                run_end_pair += 1;
+            }
+
+            let run_len_keys = run_end_pair - run_start_pair + 2;
+            total -= self.curve.bonus_for_length(run_len_keys);
+
+            if let Some((prev_dir, prev_end_pair)) = prev_run {
+                if prev_dir != dir && prev_end_pair + 1 == run_start_pair {
+                    total += self.reversal_penalty;
+                }
+            }
+
+            prev_run = Some((dir, run_end_pair));
+            i = run_end_pair + 1;
+        }
+
+        total
+    }
+}
+
+/// `Some(true)` for a roll-in pair, `Some(false)` for a roll-out pair,
+/// `None` if `curr`/`prev` aren't both mapped keys, or aren't a same-hand
+/// roll pair.
+fn classify(prev: &Option<KeyPress>, curr: &Option<KeyPress>)
+-> Option<bool>
+{
+    let prev = match *prev {
+        Some(ref k) => k,
+        None => return None,
+    };
+    let curr = match *curr {
+        Some(ref k) => k,
+        None => return None,
+    };
+    if curr.hand != prev.hand {
+        return None;
+    }
+    if is_roll_in(curr.finger, prev.finger) {
+        Some(true)
+    } else if is_roll_out(curr.finger, prev.finger) {
+        Some(false)
+    } else {
+        None
+    }
+}
@@ -11,6 +11,8 @@ use std::collections::LinkedList;
 use layout;
 use penalty;
 use annealing;
+use geometry;
+use incremental::IncrementalScorer;
 
 struct BestLayoutsEntry
 {
@@ -31,15 +33,19 @@ impl BestLayoutsEntry
 }
 
 pub fn simulate<'a>(
+    corpus:       &str,
     quartads:    &penalty::QuartadList<'a>,
     len:          usize,
     init_layout: &layout::Layout,
     penalties:   &Vec<penalty::KeyPenalty<'a>>,
+    geometry:    &geometry::KeyboardGeometry,
+    tables:      &penalty::TaperedPenaltyTables,
     debug:        bool,
     top_layouts:  usize,
     num_swaps:    usize)
+-> Result<(), String>
 {
-    let penalty = penalty::calculate_penalty(&quartads, len, init_layout, penalties, true);
+    let penalty = penalty::calculate_penalty(corpus, &quartads, len, init_layout, penalties, geometry, tables, true);
 
     if debug {
         println!("Initial layout:");
@@ -56,17 +62,32 @@ pub fn simulate<'a>(
     };
     best_layouts = list_insert_ordered(best_layouts, init_entry);
 
+    // Driving every candidate swap through `penalty::calculate_penalty`
+    // means a full corpus rescan per iteration, which dominates this
+    // loop's cost. Instead, keep an `IncrementalScorer` mirroring
+    // `accepted_layout` and use its two-key-only total -- updated in
+    // O(num positions) per swap instead of O(corpus) -- to drive
+    // acceptance. The full, exact penalty (three/four-key terms,
+    // layer-switch, shift included) is only recomputed for layouts that
+    // are actually accepted, which is far rarer than every candidate,
+    // especially as the annealing schedule cools.
+    let mut incremental = IncrementalScorer::new(corpus, init_layout, geometry, tables, debug);
+
     let mut accepted_layout = init_layout.clone();
-    let mut accepted_penalty = penalty.1;
+    let mut accepted_penalty = incremental.total() / (len as f64);
     for i in annealing::get_simulation_range() {
-        // Copy and shuffle this iteration of the layout.
+        // Copy and shuffle this iteration of the layout, mirroring each
+        // swap into the incremental scorer.
         let mut curr_layout = accepted_layout.clone();
-        curr_layout.shuffle(random::<usize>() % num_swaps + 1);
+        let num_shuffles = random::<usize>() % num_swaps + 1;
+        let mut swaps: Vec<(usize, usize)> = Vec::with_capacity(num_shuffles);
+        for _ in 0..num_shuffles {
+            let swap = curr_layout.shuffle_swap()?;
+            incremental.swap(swap.0, swap.1);
+            swaps.push(swap);
+        }
 
-        // Calculate penalty.
-        let curr_layout_copy = curr_layout.clone();
-        let penalty = penalty::calculate_penalty(&quartads, len, &curr_layout, penalties, false);
-        let scaled_penalty = penalty.1;
+        let scaled_penalty = incremental.total() / (len as f64);
 
         // Probabilistically accept worse transitions; always accept better
         // transitions.
@@ -75,12 +96,15 @@ pub fn simulate<'a>(
                 println!("Iteration {} accepted with penalty {}", i, scaled_penalty);
             }
 
-            accepted_layout = curr_layout_copy.clone();
+            accepted_layout = curr_layout.clone();
             accepted_penalty = scaled_penalty;
 
-            // Insert this layout into best layouts.
+            // Insert this layout into best layouts, with its exact
+            // penalty -- not the two-key approximation that drove
+            // acceptance -- so the printed results stay accurate.
+            let penalty = penalty::calculate_penalty(corpus, &quartads, len, &curr_layout, penalties, geometry, tables, false);
             let new_entry = BestLayoutsEntry {
-                layout: curr_layout_copy,
+                layout: curr_layout,
                 penalty: penalty.1,
             };
             best_layouts = list_insert_ordered(best_layouts, new_entry);
@@ -89,27 +113,45 @@ pub fn simulate<'a>(
             while best_layouts.len() > top_layouts {
                 best_layouts.pop_back();
             }
+        } else {
+            // Not accepted: undo the swaps (in reverse, since each is its
+            // own inverse but not generally commutative) so `incremental`
+            // keeps mirroring `accepted_layout` for the next iteration.
+            for &(a, b) in swaps.iter().rev() {
+                incremental.swap(a, b);
+            }
         }
     }
 
     for entry in best_layouts.into_iter() {
         let layout = entry.layout;
-        let penalty = penalty::calculate_penalty(&quartads, len, &layout, penalties, true);
+        let penalty = penalty::calculate_penalty(corpus, &quartads, len, &layout, penalties, geometry, tables, true);
         println!("");
         print_result(&layout, &penalty);
     }
+
+    Ok(())
 }
 
+// Unlike `simulate`, which walks a single evolving accepted layout one
+// swap at a time, `refine` enumerates every `LayoutPermutations` candidate
+// independently from `curr_layout` each round. There's no stable
+// sequential-swap state for an `IncrementalScorer` to track across that
+// enumeration, so each candidate still goes through a full
+// `penalty::calculate_penalty` pass.
 pub fn refine<'a>(
+    corpus:       &str,
     quartads:    &penalty::QuartadList<'a>,
     len:          usize,
     init_layout: &layout::Layout,
     penalties:   &Vec<penalty::KeyPenalty<'a>>,
+    geometry:    &geometry::KeyboardGeometry,
+    tables:      &penalty::TaperedPenaltyTables,
     debug:        bool,
     top_layouts:  usize,
     num_swaps:    usize)
 {
-    let penalty = penalty::calculate_penalty(&quartads, len, init_layout, penalties, true);
+    let penalty = penalty::calculate_penalty(corpus, &quartads, len, init_layout, penalties, geometry, tables, true);
 
     println!("Initial layout:");
     print_result(init_layout, &penalty);
@@ -133,7 +175,7 @@ pub fn refine<'a>(
             visited_layouts.insert(visited_layout);
 
             // Calculate penalty.
-            let penalty = penalty::calculate_penalty(&quartads, len, &layout, penalties, false);
+            let penalty = penalty::calculate_penalty(corpus, &quartads, len, &layout, penalties, geometry, tables, false);
             if debug {
                 println!("Iteration {}: {}", i, penalty.1);
                 print_result(&layout, &penalty);
@@ -155,7 +197,7 @@ pub fn refine<'a>(
         // Print the top layouts.
         for entry in best_layouts.iter() {
             let ref layout = entry.layout;
-            let penalty = penalty::calculate_penalty(&quartads, len, &layout, penalties, false);
+            let penalty = penalty::calculate_penalty(corpus, &quartads, len, &layout, penalties, geometry, tables, false);
             println!("");
             print_result(&layout, &penalty);
         }
@@ -174,7 +216,7 @@ pub fn refine<'a>(
 
     println!("");
     println!("Ultimate winner:");
-    let best_penalty = penalty::calculate_penalty(&quartads, len, &curr_layout, penalties, true);
+    let best_penalty = penalty::calculate_penalty(corpus, &quartads, len, &curr_layout, penalties, geometry, tables, true);
     print_result(&curr_layout, &best_penalty);
 }
 
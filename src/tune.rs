@@ -0,0 +1,340 @@
+/// Fits the penalty constants in `penalty.rs` to ranked layout data
+/// instead of hand-picking them, using the texel-tuning technique from
+/// chess engine development.
+///
+/// The dataset is a set of pairwise judgments: layout A preferred over
+/// layout B. For each pair we compute the model's total penalties `S_A`,
+/// `S_B` and a logistic preference probability
+/// `p = 1 / (1 + exp(K*(S_A - S_B)))` (lower penalty = preferred), then
+/// minimize the negative log-likelihood
+/// `E = -sum(y*ln(p) + (1-y)*ln(1-p))` over the penalty parameters.
+/// Since every judgment in the dataset has the preferred layout first,
+/// `y` is always 1 and the per-judgment term is just `-ln(p)`.
+///
+/// Optimization is coordinate/local descent, same as a texel tuner: for
+/// each parameter try `+-delta`, keep the change if `E` drops, and once a
+/// full sweep over every parameter yields no improvement, shrink `delta`
+/// and try again, stopping once `delta` is below `tolerance`.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+use layout::Layout;
+use geometry::KeyboardGeometry;
+use penalty;
+use penalty::{KeyPenalty, PenaltyTables, TaperedPenaltyTables};
+
+const NUM_PARAMS: usize = 19;
+const PARAM_NAMES: [&'static str; NUM_PARAMS] = [
+    "comfort_same_finger_coeff", "comfort_stretch_coeff", "comfort_same_key_pinky",
+    "speed_same_finger_coeff",   "speed_stretch_coeff",   "speed_same_key_pinky",
+    "roll_out", "roll_in", "roll_reversal", "twist", "pinky_ring_alternation",
+    "same_hand", "alternating_hand", "long_jump_sandwich", "activator_keystroke",
+    "shift_keystroke", "comfort_long_jump_surcharge", "speed_long_jump_surcharge",
+    "center_stretch_surcharge",
+];
+
+/// One pairwise judgment from the dataset: `winner` is preferred (scores
+/// lower) over `loser`.
+pub struct LayoutJudgment
+{
+    pub winner: Layout,
+    pub loser:  Layout,
+}
+
+/// The flat vector of penalty constants the tuner searches over. Mirrors
+/// the fields spread across `PenaltyTables`/`TaperedPenaltyTables`;
+/// `to_tables` is the inverse of picking them apart.
+#[derive(Clone, Copy)]
+pub struct PenaltyParams
+{
+    pub comfort_same_finger_coeff: f64,
+    pub comfort_stretch_coeff:     f64,
+    pub comfort_same_key_pinky:    f64,
+    pub speed_same_finger_coeff:   f64,
+    pub speed_stretch_coeff:       f64,
+    pub speed_same_key_pinky:      f64,
+    pub roll_out:                  f64,
+    pub roll_in:                   f64,
+    pub roll_reversal:              f64,
+    pub twist:                     f64,
+    pub pinky_ring_alternation:    f64,
+    pub same_hand:                 f64,
+    pub alternating_hand:          f64,
+    pub long_jump_sandwich:        f64,
+    pub activator_keystroke:       f64,
+    pub shift_keystroke:           f64,
+    pub comfort_long_jump_surcharge: f64,
+    pub speed_long_jump_surcharge:   f64,
+    pub center_stretch_surcharge:    f64,
+}
+
+impl PenaltyParams
+{
+    /// The constants this chunk shipped with, i.e. what `TaperedPenaltyTables::new`
+    /// builds before any tuning.
+    pub fn defaults(geometry: &KeyboardGeometry)
+    -> PenaltyParams
+    {
+        let tables = TaperedPenaltyTables::new(geometry, 0.0);
+        PenaltyParams {
+            comfort_same_finger_coeff: tables.comfort.same_finger_coeff,
+            comfort_stretch_coeff:     tables.comfort.stretch_coeff,
+            comfort_same_key_pinky:    tables.comfort.same_key_pinky,
+            speed_same_finger_coeff:   tables.speed.same_finger_coeff,
+            speed_stretch_coeff:       tables.speed.stretch_coeff,
+            speed_same_key_pinky:      tables.speed.same_key_pinky,
+            roll_out:                  tables.roll_out,
+            roll_in:                   tables.roll_in,
+            roll_reversal:             tables.roll_reversal,
+            twist:                     tables.twist,
+            pinky_ring_alternation:    tables.pinky_ring_alternation,
+            same_hand:                 tables.same_hand,
+            alternating_hand:          tables.alternating_hand,
+            long_jump_sandwich:        tables.long_jump_sandwich,
+            activator_keystroke:       tables.activator_keystroke,
+            shift_keystroke:           tables.shift_keystroke,
+            comfort_long_jump_surcharge: tables.comfort.long_jump_surcharge,
+            speed_long_jump_surcharge:   tables.speed.long_jump_surcharge,
+            center_stretch_surcharge:    tables.center_stretch_surcharge,
+        }
+    }
+
+    /// Builds the `TaperedPenaltyTables` this parameter vector describes,
+    /// for a given keyboard geometry and comfort/speed `phase`.
+    pub fn to_tables(&self, geometry: &KeyboardGeometry, phase: f64)
+    -> TaperedPenaltyTables
+    {
+        TaperedPenaltyTables {
+            comfort: PenaltyTables::generate(
+                geometry, self.comfort_same_finger_coeff, self.comfort_stretch_coeff, self.comfort_same_key_pinky,
+                self.comfort_long_jump_surcharge),
+            speed: PenaltyTables::generate(
+                geometry, self.speed_same_finger_coeff, self.speed_stretch_coeff, self.speed_same_key_pinky,
+                self.speed_long_jump_surcharge),
+            phase: phase,
+            roll_out:               self.roll_out,
+            roll_in:                self.roll_in,
+            roll_reversal:          self.roll_reversal,
+            twist:                  self.twist,
+            pinky_ring_alternation: self.pinky_ring_alternation,
+            same_hand:              self.same_hand,
+            alternating_hand:       self.alternating_hand,
+            long_jump_sandwich:     self.long_jump_sandwich,
+            activator_keystroke:    self.activator_keystroke,
+            shift_keystroke:        self.shift_keystroke,
+            center_stretch_surcharge: self.center_stretch_surcharge,
+        }
+    }
+
+    fn get(&self, idx: usize)
+    -> f64
+    {
+        match idx {
+            0  => self.comfort_same_finger_coeff,
+            1  => self.comfort_stretch_coeff,
+            2  => self.comfort_same_key_pinky,
+            3  => self.speed_same_finger_coeff,
+            4  => self.speed_stretch_coeff,
+            5  => self.speed_same_key_pinky,
+            6  => self.roll_out,
+            7  => self.roll_in,
+            8  => self.roll_reversal,
+            9  => self.twist,
+            10 => self.pinky_ring_alternation,
+            11 => self.same_hand,
+            12 => self.alternating_hand,
+            13 => self.long_jump_sandwich,
+            14 => self.activator_keystroke,
+            15 => self.shift_keystroke,
+            16 => self.comfort_long_jump_surcharge,
+            17 => self.speed_long_jump_surcharge,
+            18 => self.center_stretch_surcharge,
+            _  => panic!("parameter index {} out of range", idx),
+        }
+    }
+
+    fn set(&mut self, idx: usize, value: f64)
+    {
+        match idx {
+            0  => self.comfort_same_finger_coeff = value,
+            1  => self.comfort_stretch_coeff = value,
+            2  => self.comfort_same_key_pinky = value,
+            3  => self.speed_same_finger_coeff = value,
+            4  => self.speed_stretch_coeff = value,
+            5  => self.speed_same_key_pinky = value,
+            6  => self.roll_out = value,
+            7  => self.roll_in = value,
+            8  => self.roll_reversal = value,
+            9  => self.twist = value,
+            10 => self.pinky_ring_alternation = value,
+            11 => self.same_hand = value,
+            12 => self.alternating_hand = value,
+            13 => self.long_jump_sandwich = value,
+            14 => self.activator_keystroke = value,
+            15 => self.shift_keystroke = value,
+            16 => self.comfort_long_jump_surcharge = value,
+            17 => self.speed_long_jump_surcharge = value,
+            18 => self.center_stretch_surcharge = value,
+            _  => panic!("parameter index {} out of range", idx),
+        }
+    }
+
+    fn set_by_name(&mut self, name: &str, value: f64)
+    -> Result<(), String>
+    {
+        match PARAM_NAMES.iter().position(|n| *n == name) {
+            Some(idx) => { self.set(idx, value); Ok(()) },
+            None => Err(format!("unknown tunable parameter '{}'", name)),
+        }
+    }
+
+    /// Writes the tuned constants out as a `name value` text file, one
+    /// per line, that a later run can load back with `load`.
+    pub fn save(&self, path: &str)
+    -> Result<(), String>
+    {
+        let mut file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        for idx in 0..NUM_PARAMS {
+            writeln!(file, "{} {}", PARAM_NAMES[idx], self.get(idx)).map_err(|e| format!("{}: {}", path, e))?;
+        }
+        Ok(())
+    }
+
+    /// Loads tuned constants from a `name value` text file written by
+    /// `save`. Parameters not mentioned in the file keep `base`'s value,
+    /// so a file only needs to describe overrides.
+    pub fn load(path: &str, base: PenaltyParams)
+    -> Result<PenaltyParams, String>
+    {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut params = base;
+
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("{}: {}", path, e))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return Err(format!("{}: expected 'name value', got '{}'", path, line));
+            }
+
+            let value: f64 = fields[1].parse().map_err(|_| format!("{}: bad value in '{}'", path, line))?;
+            params.set_by_name(fields[0], value)?;
+        }
+
+        Ok(params)
+    }
+}
+
+/// A judgment's winner/loser quartad lists, built once against `corpus`
+/// since neither the layouts nor the corpus change across a `tune` call
+/// -- only the penalty weights under test do. See `tune`.
+struct PreparedJudgment<'a>
+{
+    winner: penalty::QuartadList<'a>,
+    loser:  penalty::QuartadList<'a>,
+}
+
+/// Tunes `initial` against `judgments` by coordinate descent, scoring
+/// every layout against `corpus`. `k` is the logistic steepness and
+/// `tolerance` is the step size to stop at.
+pub fn tune(
+    judgments: &Vec<LayoutJudgment>,
+    corpus:        &str,
+    geometry:      &KeyboardGeometry,
+    phase:          f64,
+    k:              f64,
+    initial:        PenaltyParams,
+    tolerance:      f64)
+-> PenaltyParams
+{
+    let penalties = penalty::init();
+
+    // Coordinate descent below re-scores every judgment on every one of
+    // the 2*NUM_PARAMS candidates per sweep; none of that depends on
+    // anything but the (fixed) layouts and corpus, so prepare each
+    // judgment's quartads once up front instead of rescanning the whole
+    // corpus on every candidate evaluation.
+    let prepared: Vec<PreparedJudgment> = judgments.iter()
+        .map(|judgment| PreparedJudgment {
+            winner: penalty::prepare_quartad_list(corpus, &judgment.winner.get_position_map()),
+            loser:  penalty::prepare_quartad_list(corpus, &judgment.loser.get_position_map()),
+        })
+        .collect();
+
+    let mut params = initial;
+    let mut error = dataset_error(&params, judgments, &prepared, corpus, geometry, phase, k, &penalties);
+    let mut delta = 1.0;
+
+    while delta > tolerance {
+        let mut improved = false;
+
+        for idx in 0..NUM_PARAMS {
+            for &sign in [1.0, -1.0].iter() {
+                let mut candidate = params;
+                candidate.set(idx, params.get(idx) + sign * delta);
+
+                let candidate_error = dataset_error(&candidate, judgments, &prepared, corpus, geometry, phase, k, &penalties);
+                if candidate_error < error {
+                    params = candidate;
+                    error = candidate_error;
+                    improved = true;
+                }
+            }
+        }
+
+        if !improved {
+            delta *= 0.5;
+        }
+    }
+
+    params
+}
+
+/// Total negative log-likelihood of `params` against every judgment.
+fn dataset_error<'a>(
+    params:     &    PenaltyParams,
+    judgments:  &    Vec<LayoutJudgment>,
+    prepared:   &    Vec<PreparedJudgment>,
+    corpus:     &    str,
+    geometry:   &    KeyboardGeometry,
+    phase:           f64,
+    k:               f64,
+    penalties:  &'a  Vec<KeyPenalty<'a>>)
+-> f64
+{
+    let tables = params.to_tables(geometry, phase);
+
+    // Clamp the logistic probability away from 0 so a single runaway
+    // judgment can't drive the log-likelihood to negative infinity.
+    const MIN_PROBABILITY: f64 = 1e-9;
+
+    let mut error = 0.0;
+    for (judgment, prepared) in judgments.iter().zip(prepared.iter()) {
+        let s_winner = score(&judgment.winner, &prepared.winner, corpus, geometry, &tables, penalties);
+        let s_loser = score(&judgment.loser, &prepared.loser, corpus, geometry, &tables, penalties);
+
+        let p = 1.0 / (1.0 + (k * (s_winner - s_loser)).exp());
+        error -= p.max(MIN_PROBABILITY).ln();
+    }
+
+    error
+}
+
+fn score<'a>(
+    layout:    &    Layout,
+    quartads:  &    penalty::QuartadList<'a>,
+    corpus:    &    str,
+    geometry:  &    KeyboardGeometry,
+    tables:    &    TaperedPenaltyTables,
+    penalties: &'a  Vec<KeyPenalty<'a>>)
+-> f64
+{
+    let (total, _, _) = penalty::calculate_penalty(
+        corpus, quartads, corpus.len(), layout, penalties, geometry, tables, false);
+    total
+}